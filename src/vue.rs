@@ -1,175 +1,2871 @@
 use std::collections::HashMap;
-use std::{env, fs};
+use std::{env, fmt, fs};
 
 use serde::Deserialize;
-use zed::lsp::{Completion, CompletionKind};
+use zed::lsp::{Completion, CompletionKind, InsertTextFormat, Symbol, SymbolKind};
 use zed::CodeLabelSpan;
 use zed_extension_api::serde_json::json;
 use zed_extension_api::settings::LspSettings;
 use zed_extension_api::{self as zed, serde_json, Result};
 
 const SERVER_PATH: &str = "node_modules/@vue/language-server/bin/vue-language-server.js";
+
+/// The paths the server script has been found at across releases of `@vue/language-server`,
+/// in order of preference.
+const SERVER_PATH_CANDIDATES: &[&str] =
+    &[SERVER_PATH, "node_modules/@vue/language-server/index.js"];
 const PACKAGE_NAME: &str = "@vue/language-server";
 
+/// A combination of Vue tooling versions that is known to work together.
+struct Toolchain {
+    server_version: &'static str,
+    plugin_version: &'static str,
+    typescript_majors: std::ops::RangeInclusive<u64>,
+    vue_majors: std::ops::RangeInclusive<u64>,
+}
+
+/// The known-good toolchains, newest first.
+///
+/// When no server version is configured, the newest toolchain supporting the project's Vue
+/// version is installed.
+const TOOLCHAINS: &[Toolchain] = &[Toolchain {
+    server_version: "2.2.8",
+    plugin_version: "2.2.8",
+    typescript_majors: 4..=5,
+    vue_majors: 2..=3,
+}];
+
+/// Returns the newest toolchain known to support the given major version of Vue.
+fn default_toolchain(vue_major_version: Option<u64>) -> &'static Toolchain {
+    TOOLCHAINS
+        .iter()
+        .find(|toolchain| {
+            vue_major_version.is_none_or(|vue_major| toolchain.vue_majors.contains(&vue_major))
+        })
+        .unwrap_or(&TOOLCHAINS[0])
+}
+
+/// Returns the toolchain for the given `@vue/language-server` version, if it is a known one.
+fn toolchain_for_server(server_version: &str) -> Option<&'static Toolchain> {
+    TOOLCHAINS
+        .iter()
+        .find(|toolchain| toolchain.server_version == server_version)
+}
+
+/// How many times an npm install is attempted before giving up.
+const NPM_INSTALL_ATTEMPTS: u32 = 3;
+
+/// The delay before retrying a failed npm install, multiplied by the number of failed attempts.
+#[cfg(not(test))]
+const NPM_INSTALL_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+#[cfg(test)]
+const NPM_INSTALL_RETRY_DELAY: std::time::Duration = std::time::Duration::ZERO;
+
+/// The npm error codes for a package version that does not exist.
+const NPM_UNKNOWN_VERSION_CODES: &[&str] = &["ETARGET", "E404"];
+
+/// The version that resolves to the latest release on the registry.
+const LATEST_VERSION: &str = "latest";
+
+/// The dist-tag of `@vue/language-server` pre-releases, installed for the `next` channel.
+const NEXT_VERSION: &str = "next";
+
+/// The directory of the managed `@vue/language-server` installation.
+///
+/// Starting with 3.0, `@vue/typescript-plugin` ships as a dependency of the server.
+const SERVER_PACKAGE_PATH: &str = "node_modules/@vue/language-server";
+
+const VUE_PACKAGE_NAME: &str = "vue";
 const TYPESCRIPT_PACKAGE_NAME: &str = "typescript";
 const TS_PLUGIN_PACKAGE_NAME: &str = "@vue/typescript-plugin";
 
+/// The languages `@vue/typescript-plugin` is registered for when none are configured, in
+/// addition to Vue itself.
+const DEFAULT_TS_PLUGIN_LANGUAGES: &[&str] = &["typescript"];
+
+/// The language identifier Vue files are registered with the TypeScript plugin under when
+/// none is configured.
+const DEFAULT_VUE_LANGUAGE_ID: &str = "vue.js";
+
+/// The highlight used for the names of deprecated completions.
+///
+/// Code labels cannot be struck through, so we dim deprecated names instead.
+const DEPRECATED_HIGHLIGHT: &str = "comment";
+
+/// The highlight used for the description in a completion's label details.
+const DESCRIPTION_HIGHLIGHT: &str = "comment";
+
+/// The hint appended to completions that add an import when accepted.
+const AUTO_IMPORT_HINT: &str = "Auto import";
+
+/// The detail prefixes TypeScript uses for completions that add an import.
+const AUTO_IMPORT_DETAIL_PREFIXES: &[&str] = &["add import from", "auto import from"];
+
+/// The highlight used for completions of registered Vue components.
+const COMPONENT_HIGHLIGHT: &str = "type";
+
+/// The detail the Vue language server gives the completions of registered components.
+const COMPONENT_DETAIL: &str = "component";
+
 /// The relative path to TypeScript's SDK.
 const TYPESCRIPT_TSDK_PATH: &str = "node_modules/typescript/lib";
 
+/// The library within a TypeScript SDK that the server loads.
+const TSSERVERLIBRARY_FILE_NAME: &str = "tsserverlibrary.js";
+
+/// The worktree-relative path to the extension settings a repository can commit.
+const PROJECT_SETTINGS_PATH: &str = ".zed/vue.json";
+
+/// The workspace settings of VS Code, which may configure `typescript.tsdk`.
+const VSCODE_SETTINGS_PATH: &str = ".vscode/settings.json";
+
+/// The Plug'n'Play runtime that Yarn generates in place of `node_modules`.
+const YARN_PNP_PATH: &str = ".pnp.cjs";
+
+/// The relative path to TypeScript's SDK as generated by `yarn dlx @yarnpkg/sdks`.
+const YARN_SDK_TSDK_PATH: &str = ".yarn/sdks/typescript/lib";
+
+/// Packages of older Vue tooling that may register a second TypeScript plugin alongside ours.
+const LEGACY_TOOLING_PACKAGES: &[&str] = &["vetur", "@volar/vue-language-server"];
+
+/// The environment variables that configure network access for Node, forwarded from the
+/// user's shell to the server.
+const NETWORK_ENV_VARS: &[&str] = &[
+    "http_proxy",
+    "https_proxy",
+    "no_proxy",
+    "NODE_EXTRA_CA_CERTS",
+];
+
+/// The configuration files that mark a worktree as a Deno project.
+const DENO_CONFIG_PATHS: &[&str] = &["deno.json", "deno.jsonc"];
+
+/// The verbosity of the extension's log output, as configured by `log.level` in the `lsp.vue`
+/// settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LogLevel {
+    Off,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    fn for_settings(settings: &VueSettings) -> Self {
+        match settings.log.level.as_deref() {
+            Some("off") => Self::Off,
+            Some("debug") => Self::Debug,
+            _ => Self::Info,
+        }
+    }
+}
+
+/// The current [`LogLevel`].
+///
+/// This is global rather than part of [`VueExtension`] so that free functions can log too.
+static LOG_LEVEL: std::sync::atomic::AtomicU8 =
+    std::sync::atomic::AtomicU8::new(LogLevel::Info as u8);
+
+/// Updates the current [`LogLevel`] from the given settings.
+fn update_log_level(settings: &VueSettings) {
+    LOG_LEVEL.store(
+        LogLevel::for_settings(settings) as u8,
+        std::sync::atomic::Ordering::Relaxed,
+    );
+}
+
+/// Returns whether messages at the given level should be logged.
+fn log_enabled(level: LogLevel) -> bool {
+    level != LogLevel::Off && level as u8 <= LOG_LEVEL.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+/// Logs a message that is shown unless logging is turned off.
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        if log_enabled(LogLevel::Info) {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Logs a message that is only shown at the `debug` log level.
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        if log_enabled(LogLevel::Debug) {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// The dependencies listed in one of a `package.json`'s dependency maps, keyed by package name.
+///
+/// The version is `None` when it is not a plain version string, such as in some generated
+/// files, since only the presence of a dependency matters for most checks.
+type Dependencies = HashMap<String, Option<String>>;
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct PackageJson {
-    #[serde(default)]
-    dependencies: HashMap<String, String>,
-    #[serde(default)]
-    dev_dependencies: HashMap<String, String>,
+    #[serde(default, deserialize_with = "deserialize_dependencies")]
+    dependencies: Dependencies,
+    #[serde(default, deserialize_with = "deserialize_dependencies")]
+    dev_dependencies: Dependencies,
+    #[serde(default, deserialize_with = "deserialize_dependencies")]
+    peer_dependencies: Dependencies,
+    #[serde(default, deserialize_with = "deserialize_dependencies")]
+    optional_dependencies: Dependencies,
+    /// The `zedVue` field, which configures this extension for the project.
+    #[serde(default, deserialize_with = "lenient")]
+    zed_vue: PackageJsonZedVue,
 }
 
-struct VueExtension {
-    did_find_server: bool,
-    typescript_tsdk_path: String,
+/// The `zedVue` field of a `package.json`, which lets a project keep its Vue tooling versions
+/// alongside its dependencies.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PackageJsonZedVue {
+    /// The `@vue/language-server` version the project requires.
+    #[serde(default, deserialize_with = "lenient")]
+    server_version: Option<String>,
 }
 
-impl VueExtension {
-    fn server_exists(&self) -> bool {
-        fs::metadata(SERVER_PATH).map_or(false, |stat| stat.is_file())
+/// Deserializes a dependency map, tolerating values that are not version strings and maps
+/// that are not objects at all.
+fn deserialize_dependencies<'de, D>(deserializer: D) -> std::result::Result<Dependencies, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let serde_json::Value::Object(dependencies) = serde_json::Value::deserialize(deserializer)?
+    else {
+        return Ok(Dependencies::new());
+    };
+
+    Ok(dependencies
+        .into_iter()
+        .map(|(package_name, version)| (package_name, version.as_str().map(str::to_owned)))
+        .collect())
+}
+
+impl PackageJson {
+    /// Returns whether the given package is listed in any of the dependency maps.
+    fn has_dependency(&self, package_name: &str) -> bool {
+        self.dependency_maps()
+            .any(|dependencies| dependencies.contains_key(package_name))
     }
 
-    fn server_script_path(
-        &mut self,
-        language_server_id: &zed::LanguageServerId,
-        worktree: &zed::Worktree,
-    ) -> Result<String> {
-        let server_exists = self.server_exists();
-        if self.did_find_server && server_exists {
-            self.install_typescript_if_needed(worktree)?;
-            self.install_ts_plugin_if_needed()?;
-            return Ok(SERVER_PATH.to_string());
+    /// Returns the version range declared for the given package, if it is a dependency with
+    /// a plain version string.
+    fn dependency_version(&self, package_name: &str) -> Option<&str> {
+        self.dependency_maps()
+            .find_map(|dependencies| dependencies.get(package_name))?
+            .as_deref()
+    }
+
+    fn dependency_maps(&self) -> impl Iterator<Item = &Dependencies> {
+        [
+            &self.dependencies,
+            &self.dev_dependencies,
+            &self.peer_dependencies,
+            &self.optional_dependencies,
+        ]
+        .into_iter()
+    }
+
+    /// Returns the packages of older Vue tooling the project still depends on.
+    fn legacy_tooling_packages(&self) -> Vec<&'static str> {
+        LEGACY_TOOLING_PACKAGES
+            .iter()
+            .copied()
+            .filter(|package_name| self.has_dependency(package_name))
+            .collect()
+    }
+}
+
+/// The root `package.json` of a worktree, parsed once and reused until the worktree changes.
+struct PackageJsonCache {
+    worktree_root: String,
+    package_json: Option<PackageJson>,
+}
+
+/// Reads and parses the `package.json` at the given worktree-relative path.
+///
+/// Returns `None` when there is no such file or it is empty, so that callers can fall back to
+/// the managed installations.
+fn read_package_json_at(worktree: &zed::Worktree, path: &str) -> VueResult<Option<PackageJson>> {
+    let Ok(package_json) = worktree.read_text_file(path) else {
+        return Ok(None);
+    };
+    // Some virtual file systems report an unreadable file as empty.
+    if package_json.trim().is_empty() {
+        return Ok(None);
+    }
+
+    serde_json::from_str(&package_json)
+        .map(Some)
+        .map_err(|err| VueExtensionError::PackageJson {
+            path: path.to_string(),
+            message: err.to_string(),
+        })
+}
+
+/// Reads every `package.json` from the given worktree-relative project directory up to, but
+/// excluding, the worktree root, nearest first.
+///
+/// Each entry is paired with the worktree-relative directory containing it.
+fn read_nested_package_jsons(
+    worktree: &zed::Worktree,
+    project_directory: &str,
+) -> VueResult<Vec<(String, PackageJson)>> {
+    let mut package_jsons = Vec::new();
+    for path in ancestor_paths(project_directory, "package.json") {
+        let Some(directory) = path.strip_suffix("/package.json") else {
+            continue;
+        };
+        if let Some(package_json) = read_package_json_at(worktree, &path)? {
+            package_jsons.push((directory.to_string(), package_json));
         }
+    }
+    Ok(package_jsons)
+}
 
-        zed::set_language_server_installation_status(
-            language_server_id,
-            &zed::LanguageServerInstallationStatus::CheckingForUpdate,
+/// Returns the worktree's root path, sanitized for use as a file system path.
+fn worktree_root(worktree: &zed::Worktree) -> std::path::PathBuf {
+    let (os, _arch) = zed::current_platform();
+    worktree_root_for_os(&worktree.root_path(), os)
+}
+
+/// Returns the given worktree root path sanitized as [`worktree_root`] would on the given OS.
+fn worktree_root_for_os(root_path: &str, os: zed::Os) -> std::path::PathBuf {
+    zed_ext::sanitize_for_os(root_path.into(), os)
+}
+
+/// Returns the absolute `location` of `@vue/typescript-plugin` in the given worktree-relative
+/// directory, given the unsanitized worktree root path and the OS to sanitize it for.
+fn ts_plugin_location_for_os(root_path: &str, directory: &str, os: zed::Os) -> String {
+    worktree_root_for_os(root_path, os)
+        .join(directory)
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Returns the absolute path to the TypeScript SDK installed in the given worktree-relative
+/// directory of the project, given the sanitized worktree root.
+fn project_tsdk_path(worktree_root: &std::path::Path, directory: &str) -> std::path::PathBuf {
+    worktree_root.join(directory).join(TYPESCRIPT_TSDK_PATH)
+}
+
+/// Returns the extension's settings: the worktree's [`PROJECT_SETTINGS_PATH`] with the
+/// `lsp.vue.settings` object layered on top.
+fn vue_settings(worktree: &zed::Worktree) -> Option<serde_json::Value> {
+    let project_settings = project_settings(worktree);
+    let user_settings = LspSettings::for_worktree("vue", worktree)
+        .ok()
+        .and_then(|settings| settings.settings);
+
+    match (project_settings, user_settings) {
+        (Some(mut settings), Some(user_settings)) => {
+            merge_json(&mut settings, user_settings);
+            Some(settings)
+        }
+        (settings, None) | (None, settings) => settings,
+    }
+}
+
+/// Reads the settings committed to the worktree in [`PROJECT_SETTINGS_PATH`].
+///
+/// A missing file is ignored, as is one that cannot be parsed, after logging why.
+fn project_settings(worktree: &zed::Worktree) -> Option<serde_json::Value> {
+    let settings = worktree.read_text_file(PROJECT_SETTINGS_PATH).ok()?;
+    serde_json::from_str(&strip_json_comments(&settings))
+        .map_err(|err| log_info!("ignoring {PROJECT_SETTINGS_PATH}: {err}"))
+        .ok()
+}
+
+/// Returns the user's initialization options for the given language server, in the order
+/// they should be merged.
+///
+/// The options under `lsp.vue` apply to every server, while the options under the server's
+/// own id (such as `lsp.vue-language-server`) take precedence for that server.
+fn user_initialization_options(
+    language_server_id: &zed::LanguageServerId,
+    worktree: &zed::Worktree,
+) -> Vec<serde_json::Value> {
+    let mut setting_keys = vec!["vue"];
+    if language_server_id.as_ref() != "vue" {
+        setting_keys.push(language_server_id.as_ref());
+    }
+
+    setting_keys
+        .into_iter()
+        .filter_map(|key| {
+            LspSettings::for_worktree(key, worktree)
+                .ok()?
+                .initialization_options
+        })
+        .collect()
+}
+
+/// Returns the paths of `file_name` in `directory` and each of its ancestors up to the
+/// worktree root, nearest first.
+///
+/// Components that would escape the worktree (such as `..`) are ignored, so the walk never
+/// reads outside of it.
+fn ancestor_paths(directory: &str, file_name: &str) -> Vec<String> {
+    let mut components = std::path::Path::new(directory)
+        .components()
+        .filter_map(|component| match component {
+            std::path::Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut paths = Vec::new();
+    loop {
+        paths.push(
+            components
+                .iter()
+                .map(String::as_str)
+                .chain([file_name])
+                .collect::<Vec<_>>()
+                .join("/"),
         );
-        // We hardcode the version to 2.2.8 since we do not support @vue/language-server 3.0 yet.
-        let version = "2.2.8".to_string();
+        if components.pop().is_none() {
+            break;
+        }
+    }
+    paths
+}
 
-        if !server_exists
-            || zed::npm_package_installed_version(PACKAGE_NAME)?.as_ref() != Some(&version)
-        {
-            zed::set_language_server_installation_status(
-                language_server_id,
-                &zed::LanguageServerInstallationStatus::Downloading,
-            );
-            let result = zed::npm_install_package(PACKAGE_NAME, &version);
-            match result {
-                Ok(()) => {
-                    if !self.server_exists() {
-                        Err(format!(
-                            "installed package '{PACKAGE_NAME}' did not contain expected path '{SERVER_PATH}'",
-                        ))?;
-                    }
-                }
-                Err(error) => {
-                    if !self.server_exists() {
-                        Err(error)?;
+/// Returns the extension's working directory, which contains the managed installations.
+fn current_dir() -> VueResult<std::path::PathBuf> {
+    env::current_dir().map_err(|error| VueExtensionError::Io {
+        action: "determine the current directory".to_string(),
+        error,
+    })
+}
+
+/// Returns the absolute path to the given script of the extension-managed server.
+fn managed_server_path(server_path: &str) -> VueResult<String> {
+    Ok(zed_ext::sanitize_windows_path(current_dir()?)
+        .join(server_path)
+        .to_string_lossy()
+        .to_string())
+}
+
+/// Returns the absolute path to the SDK of the extension-managed TypeScript.
+fn managed_tsdk_path() -> VueResult<String> {
+    Ok(zed_ext::sanitize_windows_path(current_dir()?)
+        .join(TYPESCRIPT_TSDK_PATH)
+        .to_string_lossy()
+        .to_string())
+}
+
+/// Returns whether the given path from the settings is absolute on the host.
+///
+/// The extension runs under WASI, where Windows paths such as `C:\tools` or `C:/tools` do not
+/// count as absolute, so a drive letter prefix is accepted as well.
+fn is_absolute_host_path(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    let has_drive_prefix = bytes.len() >= 3
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && matches!(bytes[2], b'/' | b'\\');
+    has_drive_prefix || std::path::Path::new(path).is_absolute()
+}
+
+/// Returns the metadata of an absolute path from the given setting, or `None` if it cannot be
+/// read.
+///
+/// The extension can usually not read outside of its own directory, so a path that cannot be
+/// inspected is trusted rather than rejected.
+fn inspect_configured_path(path: &std::path::Path, setting: &str) -> Option<fs::Metadata> {
+    fs::metadata(path)
+        .map_err(|err| {
+            log_debug!(
+                "could not verify '{}' from the configured {setting}: {err}",
+                path.display()
+            )
+        })
+        .ok()
+}
+
+/// Returns the `typescript.tsdk` configured in the worktree's `.vscode/settings.json`, resolved
+/// against the worktree root.
+///
+/// A missing or unparsable file is ignored.
+fn vscode_tsdk_path(worktree: &zed::Worktree) -> Option<String> {
+    let settings = worktree.read_text_file(VSCODE_SETTINGS_PATH).ok()?;
+    let settings: serde_json::Value = serde_json::from_str(&strip_json_comments(&settings))
+        .map_err(|err| log_info!("ignoring {VSCODE_SETTINGS_PATH}: {err}"))
+        .ok()?;
+    let tsdk = settings.get("typescript.tsdk")?.as_str()?;
+
+    Some(
+        worktree_root(worktree)
+            .join(tsdk)
+            .to_string_lossy()
+            .to_string(),
+    )
+}
+
+/// Removes `//` and `/* */` comments from the given JSONC source, leaving strings intact.
+fn strip_json_comments(source: &str) -> String {
+    let mut stripped = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            stripped.push(c);
+            match c {
+                '\\' => stripped.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                stripped.push(c);
+            }
+            ('/', Some('/')) => while chars.next_if(|&next| next != '\n').is_some() {},
+            ('/', Some('*')) => {
+                chars.next();
+                let mut previous = ' ';
+                for next in chars.by_ref() {
+                    if previous == '*' && next == '/' {
+                        break;
                     }
+                    previous = next;
                 }
             }
+            _ => stripped.push(c),
         }
+    }
+    stripped
+}
 
-        self.install_typescript_if_needed(worktree)?;
-        self.did_find_server = true;
-        Ok(SERVER_PATH.to_string())
+/// Checks that the given managed server script is a non-empty regular file, describing what
+/// is wrong with it otherwise.
+fn check_server_at(server_path: &str) -> VueResult<()> {
+    let absolute_path = current_dir()
+        .map(|current_dir| current_dir.join(server_path).to_string_lossy().to_string())
+        .unwrap_or_else(|_| server_path.to_string());
+
+    let problem = match fs::metadata(server_path) {
+        Ok(stat) if stat.is_file() && stat.len() > 0 => return Ok(()),
+        Ok(stat) if stat.is_file() => "is an empty file".to_string(),
+        Ok(stat) if stat.is_dir() => "is a directory".to_string(),
+        Ok(_) => "is not a regular file".to_string(),
+        Err(_) if is_dangling_symlink(server_path) => "is a symlink to a missing file".to_string(),
+        Err(error) => format!("could not be read: {error}"),
+    };
+    Err(VueExtensionError::InvalidServerScript {
+        path: absolute_path,
+        problem,
+    })
+}
+
+/// Returns the sorted names of the top-level entries of the given directory, if it can be read.
+fn directory_entries(path: &str) -> Option<Vec<String>> {
+    let mut entries: Vec<String> = fs::read_dir(path)
+        .ok()?
+        .filter_map(|entry| Some(entry.ok()?.file_name().to_string_lossy().to_string()))
+        .collect();
+    entries.sort();
+    Some(entries)
+}
+
+/// Returns whether the given path is a symlink whose target does not exist.
+fn is_dangling_symlink(path: &str) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|stat| stat.file_type().is_symlink())
+        && fs::metadata(path).is_err()
+}
+
+/// Returns whether the worktree is a Yarn Plug'n'Play project.
+fn uses_yarn_pnp(worktree: &zed::Worktree) -> bool {
+    worktree.read_text_file(YARN_PNP_PATH).is_ok()
+}
+
+/// Returns whether the worktree is a Deno project.
+fn uses_deno(worktree: &zed::Worktree) -> bool {
+    DENO_CONFIG_PATHS
+        .iter()
+        .any(|path| worktree.read_text_file(path).is_ok())
+}
+
+/// The ways in which resolving and installing the Vue tooling can fail.
+///
+/// The install flow branches on these, and they are only turned into the `String` the
+/// extension API expects where they leave the extension.
+#[derive(Debug)]
+enum VueExtensionError {
+    /// Looking up a package in the npm registry failed.
+    Registry {
+        package_name: String,
+        message: String,
+    },
+    /// Installing a package failed, usually because of the network.
+    Install {
+        package_name: String,
+        version: String,
+        message: String,
+    },
+    /// The requested version of a package does not exist in the registry.
+    UnknownVersion {
+        package_name: String,
+        version: String,
+        message: String,
+    },
+    /// A package is not installed and cannot be downloaded in offline mode.
+    Offline {
+        package_name: String,
+        version: String,
+    },
+    /// A `package.json` could not be parsed.
+    PackageJson { path: String, message: String },
+    /// No usable Node binary was found, for the given reason.
+    MissingNode(String),
+    /// No usable server script was found at any of the candidate paths, for the given reasons.
+    MissingServer(String),
+    /// A server script is not a usable file, for the given reason.
+    InvalidServerScript { path: String, problem: String },
+    /// A package was installed, but contains no usable server script.
+    UnusablePackage {
+        package_name: String,
+        message: String,
+    },
+    /// A version setting is neither an exact version nor a dist-tag.
+    InvalidVersion {
+        package_name: String,
+        message: String,
+    },
+    /// The configured `typescript_plugin.location` is not usable, for the given reason.
+    InvalidPluginLocation { location: String, problem: String },
+    /// The worktree is a Deno project, which is not supported yet.
+    DenoProject,
+    /// A file system operation failed.
+    Io {
+        action: String,
+        error: std::io::Error,
+    },
+}
+
+impl fmt::Display for VueExtensionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Registry {
+                package_name,
+                message,
+            } => write!(
+                f,
+                "failed to look up {package_name} in the npm registry: {message}"
+            ),
+            Self::Install {
+                package_name,
+                version,
+                message,
+            } => write!(f, "failed to install {package_name}@{version}: {message}"),
+            Self::UnknownVersion {
+                package_name,
+                version,
+                message,
+            } => write!(
+                f,
+                "{package_name}@{version} does not exist in the npm registry: {message}"
+            ),
+            Self::Offline {
+                package_name,
+                version,
+            } => write!(
+                f,
+                "{package_name}@{version} is not installed and cannot be downloaded in offline \
+                mode, disable `offline` in the lsp.vue settings to install it"
+            ),
+            Self::PackageJson { path, message } => write!(f, "failed to parse {path}: {message}"),
+            Self::MissingNode(reason) => {
+                write!(f, "the Vue language server requires Node.js, but {reason}")
+            }
+            Self::MissingServer(reasons) => write!(f, "no usable server script found: {reasons}"),
+            Self::InvalidServerScript { path, problem } => write!(f, "'{path}' {problem}"),
+            Self::UnusablePackage {
+                package_name,
+                message,
+            } => write!(
+                f,
+                "installed package '{package_name}' is unusable, {message}"
+            ),
+            Self::InvalidVersion {
+                package_name,
+                message,
+            } => write!(f, "invalid {package_name} version setting: {message}"),
+            Self::InvalidPluginLocation { location, problem } => write!(
+                f,
+                "the configured typescript_plugin.location '{location}' {problem}"
+            ),
+            Self::DenoProject => write!(
+                f,
+                "Deno projects are not supported yet. Set \"nodeModulesDir\" in your deno.json \
+                and add {PACKAGE_NAME} to the project's dependencies to use it from node_modules"
+            ),
+            Self::Io { action, error } => write!(f, "failed to {action}: {error}"),
+        }
     }
+}
 
-    /// Returns whether a local copy of TypeScript exists in the worktree.
-    fn typescript_exists_for_worktree(&self, worktree: &zed::Worktree) -> Result<bool> {
-        let package_json = worktree.read_text_file("package.json")?;
-        let package_json: PackageJson = serde_json::from_str(&package_json)
-            .map_err(|err| format!("failed to parse package.json: {err}"))?;
+impl VueExtensionError {
+    /// Classifies the error message of a failed npm install.
+    ///
+    /// npm reports a version that does not exist with the `ETARGET` or `E404` codes, which
+    /// retrying cannot fix.
+    fn install(package_name: &str, version: &str, message: String) -> Self {
+        let package_name = package_name.to_string();
+        let version = version.to_string();
+        if NPM_UNKNOWN_VERSION_CODES
+            .iter()
+            .any(|code| message.contains(code))
+        {
+            Self::UnknownVersion {
+                package_name,
+                version,
+                message,
+            }
+        } else {
+            Self::Install {
+                package_name,
+                version,
+                message,
+            }
+        }
+    }
 
-        let dev_dependencies = &package_json.dev_dependencies;
-        let dependencies = &package_json.dependencies;
+    /// Returns whether the failed operation may succeed when retried.
+    fn is_transient(&self) -> bool {
+        matches!(self, Self::Install { .. })
+    }
+}
 
-        // Since the extension is not allowed to read the filesystem within the project
-        // except through the worktree (which does not contains `node_modules`), we check
-        // the `package.json` to see if `typescript` is listed in the dependencies.
-        Ok(dev_dependencies.contains_key(TYPESCRIPT_PACKAGE_NAME)
-            || dependencies.contains_key(TYPESCRIPT_PACKAGE_NAME))
+impl From<VueExtensionError> for String {
+    fn from(error: VueExtensionError) -> Self {
+        error.to_string()
     }
+}
+
+/// The result of the parts of the install flow that fail with a [`VueExtensionError`].
+type VueResult<T> = std::result::Result<T, VueExtensionError>;
+
+/// The npm and installation status functions of the Zed API that the install flow uses.
+///
+/// The install flow goes through this trait rather than calling the host directly, so that
+/// it can be driven by something other than Zed.
+trait Npm: Send + Sync {
+    fn installed_version(&self, package_name: &str) -> VueResult<Option<String>>;
+    fn latest_version(&self, package_name: &str) -> VueResult<String>;
+    fn install(&self, package_name: &str, version: &str) -> VueResult<()>;
+    fn set_installation_status(
+        &self,
+        language_server_id: &zed::LanguageServerId,
+        status: &zed::LanguageServerInstallationStatus,
+    );
+}
+
+/// The [`Npm`] backed by Zed.
+struct ZedNpm;
+
+impl Npm for ZedNpm {
+    fn installed_version(&self, package_name: &str) -> VueResult<Option<String>> {
+        zed::npm_package_installed_version(package_name).map_err(|message| {
+            VueExtensionError::Registry {
+                package_name: package_name.to_string(),
+                message,
+            }
+        })
+    }
+
+    fn latest_version(&self, package_name: &str) -> VueResult<String> {
+        zed::npm_package_latest_version(package_name).map_err(|message| {
+            VueExtensionError::Registry {
+                package_name: package_name.to_string(),
+                message,
+            }
+        })
+    }
+
+    fn install(&self, package_name: &str, version: &str) -> VueResult<()> {
+        zed::npm_install_package(package_name, version)
+            .map_err(|message| VueExtensionError::install(package_name, version, message))
+    }
+
+    fn set_installation_status(
+        &self,
+        language_server_id: &zed::LanguageServerId,
+        status: &zed::LanguageServerInstallationStatus,
+    ) {
+        zed::set_language_server_installation_status(language_server_id, status);
+    }
+}
+
+struct VueExtension {
+    npm: Box<dyn Npm>,
+    did_find_server: bool,
+    /// The root of the worktree the server was last resolved for.
+    server_worktree_root: Option<String>,
+    /// The version of `@vue/language-server` that was resolved when the server was found, or
+    /// the version range the project declares for its own copy.
+    server_version: Option<String>,
+    /// The TypeScript SDK resolved while installing TypeScript, if it has been resolved.
+    typescript_tsdk_path: Option<String>,
+    /// Whether we already warned that no TypeScript SDK was resolved.
+    did_warn_about_tsdk: bool,
+    package_json_cache: Option<PackageJsonCache>,
+    /// Labels are computed without access to the worktree, so the settings for them are read
+    /// whenever the server is started.
+    completion_settings: CompletionSettings,
+    /// Whether the toolchain is being reinstalled regardless of the installed versions.
+    force_reinstall: bool,
+    /// Whether the `reinstall` setting has already been honored in this session.
+    did_force_reinstall: bool,
+    /// The major version of Vue the project depends on, if it declares it.
+    vue_major_version: Option<u64>,
+    /// The `@vue/language-server` version requested by the worktree's `package.json`, if any.
+    project_server_version: Option<String>,
+    /// Whether the `offline` setting forbids network access, as of the last server start.
+    offline: bool,
+    /// Whether installing `@vue/typescript-plugin` failed, in which case it is not advertised.
+    ts_plugin_install_failed: bool,
+    /// The latest versions of npm packages, as looked up during this session.
+    latest_versions: HashMap<String, String>,
+}
+
+/// The extension's settings, as merged by [`vue_settings`].
+///
+/// Every field is deserialized leniently, so a value of the wrong type is treated as unset
+/// rather than discarding the other settings.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct VueSettings {
+    #[serde(deserialize_with = "lenient")]
+    log: LogSettings,
+    /// The worktree-relative directory containing the Vue project, the worktree root if empty.
+    #[serde(alias = "projectDirectory", deserialize_with = "lenient")]
+    project_directory: String,
+    /// Whether network access is forbidden, so that only installed packages are used.
+    #[serde(deserialize_with = "lenient")]
+    offline: bool,
+    /// Whether the toolchain is reinstalled once, regardless of the installed versions.
+    #[serde(deserialize_with = "lenient")]
+    reinstall: bool,
+    /// The npm registry forwarded to the server process.
+    #[serde(deserialize_with = "lenient")]
+    registry: Option<String>,
+    /// Environment variables to launch the server with.
+    #[serde(deserialize_with = "lenient_map")]
+    env: HashMap<String, String>,
+    /// Extra arguments to pass to the server.
+    #[serde(deserialize_with = "lenient_list")]
+    arguments: Vec<String>,
+    #[serde(deserialize_with = "lenient")]
+    node: NodeSettings,
+    #[serde(alias = "languageServer", deserialize_with = "lenient")]
+    language_server: LanguageServerSettings,
+    #[serde(deserialize_with = "lenient")]
+    typescript: TypeScriptSettings,
+    #[serde(alias = "typescriptPlugin", deserialize_with = "lenient")]
+    typescript_plugin: TypeScriptPluginSettings,
+    #[serde(deserialize_with = "lenient")]
+    vue: VueServerSettings,
+    /// The `vue` settings as they were given, which are passed on to the server as its
+    /// `vue.*` workspace configuration.
+    #[serde(skip)]
+    vue_configuration: Option<serde_json::Value>,
+    #[serde(alias = "inlayHints", deserialize_with = "lenient")]
+    inlay_hints: InlayHintSettings,
+    #[serde(alias = "autoImports", deserialize_with = "lenient")]
+    auto_imports: AutoImportSettings,
+    #[serde(deserialize_with = "lenient")]
+    html: HtmlSettings,
+    #[serde(deserialize_with = "lenient")]
+    completions: CompletionSettings,
+    #[serde(deserialize_with = "lenient")]
+    format: FormatSettings,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct LogSettings {
+    /// `off`, `info` or `debug`.
+    #[serde(deserialize_with = "lenient")]
+    level: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct NodeSettings {
+    /// The Node binary to launch the server with, instead of the one used by Zed.
+    #[serde(deserialize_with = "lenient")]
+    path: Option<String>,
+}
 
-    fn install_typescript_if_needed(&mut self, worktree: &zed::Worktree) -> Result<()> {
-        if self
-            .typescript_exists_for_worktree(worktree)
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct LanguageServerSettings {
+    /// The pinned version of `@vue/language-server`.
+    #[serde(deserialize_with = "lenient")]
+    version: Option<String>,
+    /// `stable` or `next`, used when no version is pinned.
+    #[serde(deserialize_with = "lenient")]
+    channel: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TypeScriptSettings {
+    #[serde(deserialize_with = "lenient")]
+    tsdk: Option<String>,
+    /// The pinned version of the extension-managed TypeScript.
+    #[serde(deserialize_with = "lenient")]
+    version: Option<String>,
+    /// The tsserver plugins to register after `@vue/typescript-plugin`.
+    #[serde(alias = "additionalPlugins", deserialize_with = "lenient_list")]
+    additional_plugins: Vec<serde_json::Value>,
+    /// The memory limit in megabytes for the tsserver the plugin runs in.
+    #[serde(alias = "maxServerMemory", deserialize_with = "lenient")]
+    max_server_memory: Option<u64>,
+    #[serde(alias = "preferWorkspaceVersion", deserialize_with = "lenient")]
+    prefer_workspace_version: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TypeScriptPluginSettings {
+    #[serde(deserialize_with = "lenient")]
+    enabled: Option<bool>,
+    /// The pinned version of the extension-managed `@vue/typescript-plugin`.
+    #[serde(deserialize_with = "lenient")]
+    version: Option<String>,
+    /// The `location` to advertise for the plugin instead of the computed one.
+    #[serde(deserialize_with = "lenient")]
+    location: Option<String>,
+    /// The id of the TypeScript server to register the plugin with, or `auto`.
+    #[serde(deserialize_with = "lenient")]
+    target: Option<String>,
+    #[serde(deserialize_with = "lenient")]
+    languages: Option<Vec<String>>,
+    #[serde(alias = "additionalLanguages", deserialize_with = "lenient_list")]
+    additional_languages: Vec<String>,
+    #[serde(alias = "vueLanguageId", deserialize_with = "lenient")]
+    vue_language_id: Option<String>,
+}
+
+/// The `vue` settings, which are named after the server's own options.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct VueServerSettings {
+    #[serde(deserialize_with = "lenient")]
+    hybrid_mode: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct InlayHintSettings {
+    #[serde(alias = "parameterNames", deserialize_with = "lenient")]
+    parameter_names: Option<bool>,
+    #[serde(alias = "parameterTypes", deserialize_with = "lenient")]
+    parameter_types: Option<bool>,
+    #[serde(alias = "variableTypes", deserialize_with = "lenient")]
+    variable_types: Option<bool>,
+    #[serde(alias = "propertyDeclarationTypes", deserialize_with = "lenient")]
+    property_declaration_types: Option<bool>,
+    #[serde(alias = "functionLikeReturnTypes", deserialize_with = "lenient")]
+    function_like_return_types: Option<bool>,
+    #[serde(alias = "enumMemberValues", deserialize_with = "lenient")]
+    enum_member_values: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct AutoImportSettings {
+    /// How import paths are written, e.g. `relative` or `non-relative`.
+    #[serde(alias = "moduleSpecifier", deserialize_with = "lenient")]
+    module_specifier: Option<String>,
+    /// The quotes around import paths, e.g. `single` or `double`.
+    #[serde(alias = "quoteStyle", deserialize_with = "lenient")]
+    quote_style: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct HtmlSettings {
+    /// Worktree-relative paths to custom HTML data files, e.g. for web components.
+    #[serde(alias = "customData", deserialize_with = "lenient_list")]
+    custom_data: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct FormatSettings {
+    #[serde(alias = "initialIndent", deserialize_with = "lenient")]
+    initial_indent: InitialIndentSettings,
+    /// The line length the template is wrapped at.
+    #[serde(alias = "printWidth", deserialize_with = "lenient")]
+    print_width: Option<u64>,
+    /// How the template's attributes are wrapped, e.g. `force-aligned`.
+    #[serde(alias = "wrapAttributes", deserialize_with = "lenient")]
+    wrap_attributes: Option<String>,
+}
+
+/// Whether the contents of each block of a Vue file are indented.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct InitialIndentSettings {
+    #[serde(deserialize_with = "lenient")]
+    template: Option<bool>,
+    #[serde(deserialize_with = "lenient")]
+    script: Option<bool>,
+    #[serde(deserialize_with = "lenient")]
+    style: Option<bool>,
+}
+
+impl VueSettings {
+    /// Reads the settings for the given worktree, which is done once per extension hook.
+    fn for_worktree(worktree: &zed::Worktree) -> Self {
+        vue_settings(worktree)
+            .map(Self::from_value)
             .unwrap_or_default()
-        {
-            println!("found local TypeScript installation at '{TYPESCRIPT_TSDK_PATH}'");
-            return Ok(());
+    }
+
+    fn from_value(settings: serde_json::Value) -> Self {
+        let vue_configuration = settings.get("vue").cloned();
+        Self {
+            vue_configuration,
+            ..serde_json::from_value(settings).unwrap_or_default()
         }
+    }
+
+    /// Returns whether hybrid mode is enabled.
+    ///
+    /// Hybrid mode is disabled by default.
+    fn hybrid_mode(&self) -> bool {
+        self.vue.hybrid_mode
+    }
+}
+
+/// Deserializes a value, falling back to its default when it has an unexpected type.
+fn lenient<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::de::DeserializeOwned + Default,
+{
+    let value = serde_json::Value::deserialize(deserializer)?;
+    Ok(serde_json::from_value(value).unwrap_or_default())
+}
+
+/// Deserializes an object, dropping the entries with a value of an unexpected type.
+fn lenient_map<'de, D, T>(deserializer: D) -> std::result::Result<HashMap<String, T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::de::DeserializeOwned,
+{
+    let serde_json::Value::Object(entries) = serde_json::Value::deserialize(deserializer)? else {
+        return Ok(HashMap::new());
+    };
+    Ok(entries
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, serde_json::from_value(value).ok()?)))
+        .collect())
+}
 
-        let installed_typescript_version =
-            zed::npm_package_installed_version(TYPESCRIPT_PACKAGE_NAME)?;
-        let latest_typescript_version = zed::npm_package_latest_version(TYPESCRIPT_PACKAGE_NAME)?;
+/// Deserializes an array, dropping the items of an unexpected type.
+fn lenient_list<'de, D, T>(deserializer: D) -> std::result::Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::de::DeserializeOwned,
+{
+    let serde_json::Value::Array(items) = serde_json::Value::deserialize(deserializer)? else {
+        return Ok(Vec::new());
+    };
+    Ok(items
+        .into_iter()
+        .filter_map(|item| serde_json::from_value(item).ok())
+        .collect())
+}
+
+/// The `completions` settings that affect how completion labels are rendered.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+struct CompletionSettings {
+    /// The maximum number of characters of a completion's detail to display.
+    #[serde(alias = "maxDetailLength", deserialize_with = "lenient")]
+    max_detail_length: Option<usize>,
+    /// Highlight names to use for completion kinds, keyed by the kind's name (e.g. `Variable`).
+    #[serde(deserialize_with = "lenient_map")]
+    highlights: HashMap<String, String>,
+    /// Whether to display a completion's detail, keyed by the kind's name.
+    #[serde(alias = "showDetail", deserialize_with = "lenient_map")]
+    show_detail: HashMap<String, bool>,
+    /// Whether completions get the extension's labels, rather than Zed's default rendering.
+    ///
+    /// Enabled unless set to `false`.
+    #[serde(alias = "customLabels", deserialize_with = "lenient")]
+    custom_labels: Option<bool>,
+}
+
+impl CompletionSettings {
+    /// Whether the detail of completions of the given kind should be displayed.
+    ///
+    /// Keyword details are hidden by default, as they rarely carry anything useful.
+    fn shows_detail_for(&self, kind: &CompletionKind) -> bool {
+        completion_kind_name(kind)
+            .and_then(|name| self.show_detail.get(name).copied())
+            .unwrap_or(!matches!(kind, CompletionKind::Keyword))
+    }
+
+    /// Returns the highlight configured for the given completion kind, if any.
+    fn highlight_for(&self, kind: &CompletionKind) -> Option<&str> {
+        self.highlights
+            .get(completion_kind_name(kind)?)
+            .map(String::as_str)
+    }
+
+    /// Returns the label to display for the given completion, or `None` to leave it to Zed.
+    fn label_for(&self, completion: Completion) -> Option<zed::CodeLabel> {
+        if self.custom_labels == Some(false) {
+            return None;
+        }
+        let kind = completion.kind.as_ref()?;
+        let highlight_name = match self.highlight_for(kind) {
+            Some(highlight_name) => highlight_name,
+            None => default_completion_highlight(kind, &completion)?,
+        };
+        let highlight_name = if is_deprecated_completion(&completion) {
+            DEPRECATED_HIGHLIGHT
+        } else {
+            highlight_name
+        };
+        let is_auto_import = is_auto_import_completion(&completion);
 
-        if installed_typescript_version.as_ref() != Some(&latest_typescript_version) {
-            println!("installing {TYPESCRIPT_PACKAGE_NAME}@{latest_typescript_version}");
-            zed::npm_install_package(TYPESCRIPT_PACKAGE_NAME, &latest_typescript_version)?;
+        // Snippet labels may contain placeholders, which are shown as the text they insert.
+        let is_snippet = matches!(kind, CompletionKind::Snippet)
+            || matches!(
+                completion.insert_text_format,
+                Some(InsertTextFormat::Snippet)
+            );
+        let label = if is_snippet {
+            strip_snippet_placeholders(&completion.label)
         } else {
-            println!("typescript already installed");
+            completion.label
+        };
+        let len = label.len();
+        let name_span = CodeLabelSpan::literal(label, Some(highlight_name.to_string()));
+        let max_detail_length = self.max_detail_length;
+        let mut spans = vec![name_span];
+        if self.shows_detail_for(kind) {
+            match completion.label_details {
+                // Label details are what VS Code shows: a suffix such as the signature right
+                // after the label, and a dimmed description such as the import source.
+                Some(label_details)
+                    if label_details.detail.is_some() || label_details.description.is_some() =>
+                {
+                    if let Some(detail) = label_details.detail {
+                        spans.push(CodeLabelSpan::literal(
+                            truncate_detail(detail, max_detail_length),
+                            None,
+                        ));
+                    }
+                    if let Some(description) = label_details.description {
+                        spans.push(CodeLabelSpan::literal(" ", None));
+                        spans.push(CodeLabelSpan::literal(
+                            truncate_detail(description, max_detail_length),
+                            Some(DESCRIPTION_HIGHLIGHT.to_string()),
+                        ));
+                    }
+                }
+                _ => {
+                    if let Some(detail) = completion.detail {
+                        spans.push(CodeLabelSpan::literal(" ", None));
+                        spans.push(CodeLabelSpan::literal(
+                            truncate_detail(detail, max_detail_length),
+                            None,
+                        ));
+                    }
+                }
+            }
+        }
+        // Accepting an auto-import also edits the imports, which the label should make clear.
+        if is_auto_import {
+            spans.push(CodeLabelSpan::literal(" ", None));
+            spans.push(CodeLabelSpan::literal(
+                AUTO_IMPORT_HINT,
+                Some(DESCRIPTION_HIGHLIGHT.to_string()),
+            ));
         }
 
-        self.typescript_tsdk_path = zed_ext::sanitize_windows_path(env::current_dir().unwrap())
-            .join(TYPESCRIPT_TSDK_PATH)
-            .to_string_lossy()
-            .to_string();
+        Some(zed::CodeLabel {
+            code: Default::default(),
+            spans,
+            filter_range: (0..len).into(),
+        })
+    }
+}
 
-        Ok(())
+impl VueExtension {
+    fn server_exists(&self) -> bool {
+        self.find_server().is_ok()
+    }
+
+    /// Returns the first of the [`SERVER_PATH_CANDIDATES`] that is a usable server script,
+    /// describing what is wrong with each of them otherwise.
+    fn find_server(&self) -> VueResult<&'static str> {
+        let mut errors = Vec::new();
+        for server_path in SERVER_PATH_CANDIDATES {
+            match check_server_at(server_path) {
+                Ok(()) => return Ok(server_path),
+                Err(error) => errors.push(error),
+            }
+        }
+        let reasons = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+        Err(VueExtensionError::MissingServer(reasons.join("; ")))
+    }
+
+    /// Returns the absolute path to the server script to launch, which is either the project's
+    /// own copy or the extension-managed one.
+    fn server_script_path(
+        &mut self,
+        language_server_id: &zed::LanguageServerId,
+        worktree: &zed::Worktree,
+        settings: &VueSettings,
+    ) -> VueResult<String> {
+        self.offline = settings.offline;
+        self.warn_about_legacy_tooling(worktree);
+
+        // Under Yarn Plug'n'Play the project's copy is not available as a plain file, so we
+        // always use the managed installation there.
+        if !uses_yarn_pnp(worktree) {
+            if let Some((local_server_path, local_version)) =
+                self.local_server(worktree, settings).unwrap_or_default()
+            {
+                log_info!("found local {PACKAGE_NAME} installation at '{local_server_path}'");
+                // The options we send depend on the server's major version, so a version left
+                // over from the managed installation must not be used for the project's copy.
+                self.server_version = local_version;
+                self.install_typescript_if_needed(language_server_id, worktree, settings)?;
+                return Ok(local_server_path);
+            }
+        }
+
+        if uses_deno(worktree) {
+            return Err(VueExtensionError::DenoProject);
+        }
+
+        self.vue_major_version = self.detect_vue_major_version(worktree);
+        if let Some(vue_major_version) = self.vue_major_version {
+            log_info!("detected a Vue {vue_major_version} project");
+        }
+        self.project_server_version = self
+            .package_json(worktree)
+            .ok()
+            .flatten()
+            .and_then(|package_json| package_json.zed_vue.server_version.clone());
+
+        let version = self.requested_server_version(settings);
+        validate_version(&version).map_err(|message| VueExtensionError::InvalidVersion {
+            package_name: PACKAGE_NAME.to_string(),
+            message,
+        })?;
+
+        if settings.reinstall && !self.did_force_reinstall {
+            log_info!(
+                "reinstalling {PACKAGE_NAME}, {TYPESCRIPT_PACKAGE_NAME} \
+                and {TS_PLUGIN_PACKAGE_NAME}"
+            );
+            self.did_find_server = false;
+            self.did_force_reinstall = true;
+            self.force_reinstall = true;
+        }
+
+        // Another worktree may need a different toolchain, so it is resolved from scratch.
+        let worktree_root = worktree.root_path();
+        if self.server_worktree_root.as_ref() != Some(&worktree_root) {
+            self.did_find_server = false;
+            self.server_worktree_root = Some(worktree_root);
+        }
+
+        let server_exists = self.server_exists();
+        let is_requested_version = version == LATEST_VERSION
+            || version == NEXT_VERSION
+            || self.server_version.as_ref() == Some(&version);
+        if self.did_find_server && server_exists && is_requested_version {
+            self.install_typescript_if_needed(language_server_id, worktree, settings)?;
+            if self.needs_ts_plugin_install(settings) {
+                self.install_ts_plugin_or_degrade(language_server_id, settings);
+            }
+            return managed_server_path(self.find_server()?);
+        }
+
+        self.npm.set_installation_status(
+            language_server_id,
+            &zed::LanguageServerInstallationStatus::CheckingForUpdate,
+        );
+        let version = if version == LATEST_VERSION {
+            self.npm_package_latest_version(PACKAGE_NAME)?
+        } else {
+            version
+        };
+
+        // A dangling symlink would keep the install from writing the server, so it is
+        // removed up front.
+        for server_path in SERVER_PATH_CANDIDATES {
+            if is_dangling_symlink(server_path) {
+                log_info!("removing dangling symlink '{server_path}'");
+                fs::remove_file(server_path).map_err(|error| VueExtensionError::Io {
+                    action: format!("remove the dangling symlink '{server_path}'"),
+                    error,
+                })?;
+            }
+        }
+
+        let outcome = install_server_package(
+            self.npm.as_ref(),
+            &|status| {
+                self.npm
+                    .set_installation_status(language_server_id, &status)
+            },
+            ServerInstall {
+                version,
+                force_reinstall: self.force_reinstall,
+                offline: self.offline,
+                server_exists,
+            },
+        )?;
+        let version = match outcome {
+            ServerInstallOutcome::Installed(version) => {
+                if let Err(error) = self.find_server() {
+                    let contents = match directory_entries(SERVER_PACKAGE_PATH) {
+                        Some(entries) => format!(" (the package contains: {})", entries.join(", ")),
+                        None => format!(" ('{SERVER_PACKAGE_PATH}' could not be listed)"),
+                    };
+                    return Err(VueExtensionError::UnusablePackage {
+                        package_name: PACKAGE_NAME.to_string(),
+                        message: format!("{error}{contents}"),
+                    });
+                }
+                Some(version)
+            }
+            ServerInstallOutcome::Kept(version) => Some(version),
+            // The requested version is not the one that runs, so it must not be recorded.
+            ServerInstallOutcome::KeptExisting(installed_version) => installed_version,
+        };
+
+        self.install_typescript_if_needed(language_server_id, worktree, settings)?;
+        if self.force_reinstall && self.needs_ts_plugin_install(settings) {
+            self.install_ts_plugin_or_degrade(language_server_id, settings);
+        }
+        // The dist-tag cannot be looked up beforehand, so we record what it installed.
+        let version = if version.as_deref() == Some(NEXT_VERSION) {
+            self.npm.installed_version(PACKAGE_NAME)?.or(version)
+        } else {
+            version
+        };
+        let server_path = self.find_server()?;
+        log_debug!(
+            "resolved {PACKAGE_NAME}@{} at '{server_path}'",
+            version.as_deref().unwrap_or("unknown version")
+        );
+        self.force_reinstall = false;
+        self.did_find_server = true;
+        self.server_version = version;
+        managed_server_path(server_path)
+    }
+
+    /// Returns the latest version of the given npm package.
+    ///
+    /// The lookup is only done once per package for the lifetime of the extension.
+    fn npm_package_latest_version(&mut self, package_name: &str) -> VueResult<String> {
+        if let Some(version) = self.latest_versions.get(package_name) {
+            return Ok(version.clone());
+        }
+        // Without the network, the installed version is the latest one we can use.
+        if self.offline {
+            return self.npm.installed_version(package_name)?.ok_or_else(|| {
+                VueExtensionError::Offline {
+                    package_name: package_name.to_string(),
+                    version: LATEST_VERSION.to_string(),
+                }
+            });
+        }
+
+        let version = self.npm.latest_version(package_name)?;
+        self.latest_versions
+            .insert(package_name.to_string(), version.clone());
+        Ok(version)
+    }
+
+    /// Returns the version of `@vue/language-server` requested for the worktree.
+    ///
+    /// A `zedVue.serverVersion` in the project's `package.json` takes precedence over the
+    /// version in the `lsp.vue` settings. Without a pinned version, the `next` channel installs
+    /// the latest pre-release, and the default `stable` channel the [`default_toolchain`] for
+    /// the project's Vue version.
+    fn requested_server_version(&self, settings: &VueSettings) -> String {
+        self.project_server_version
+            .clone()
+            .or_else(|| settings.language_server.version.clone())
+            .or_else(|| {
+                (settings.language_server.channel.as_deref() == Some(NEXT_VERSION))
+                    .then(|| NEXT_VERSION.to_string())
+            })
+            .unwrap_or_else(|| {
+                default_toolchain(self.vue_major_version)
+                    .server_version
+                    .to_string()
+            })
+    }
+
+    /// Returns the Node binary to launch the server with.
+    ///
+    /// A `node.path` in the `lsp.vue` settings takes precedence over the Node binary used by Zed.
+    fn node_binary_path(worktree: &zed::Worktree, settings: &VueSettings) -> VueResult<String> {
+        let Some(node_path) = &settings.node.path else {
+            return zed::node_binary_path().map_err(|err| {
+                VueExtensionError::MissingNode(format!(
+                    "Zed could not locate a Node binary ({err}). \
+                    Install Node.js or set node.path in the lsp.vue settings"
+                ))
+            });
+        };
+
+        // The extension cannot access arbitrary paths on the host, so we resolve the binary
+        // through the worktree, which accepts both absolute paths and binary names.
+        worktree.which(node_path).ok_or_else(|| {
+            VueExtensionError::MissingNode(format!(
+                "the configured node.path '{node_path}' does not exist or is not executable"
+            ))
+        })
+    }
+
+    /// Returns the environment variables to launch the server with.
+    fn server_env(worktree: &zed::Worktree, settings: &VueSettings) -> Vec<(String, String)> {
+        let mut server_env = Vec::new();
+        // Zed does not pass the user's shell environment on by itself, so the proxy and
+        // certificate variables needed behind corporate networks are forwarded explicitly.
+        for (key, value) in worktree.shell_env() {
+            if NETWORK_ENV_VARS
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(&key))
+            {
+                server_env.push((key, value));
+            }
+        }
+        // Packages are installed by Zed itself, which offers no way to choose the registry,
+        // so the best we can do is to forward it to the server process.
+        if let Some(registry) = &settings.registry {
+            server_env.push(("npm_config_registry".to_string(), registry.clone()));
+        }
+
+        for (key, value) in &settings.env {
+            server_env.retain(|(existing_key, _)| existing_key != key);
+            server_env.push((key.clone(), value.clone()));
+        }
+
+        server_env
+    }
+
+    /// Returns the version of the resolved `@vue/language-server`.
+    ///
+    /// Before the server has been resolved, the requested version is used instead.
+    fn resolved_server_version(&self, settings: &VueSettings) -> String {
+        self.server_version
+            .clone()
+            .unwrap_or_else(|| self.requested_server_version(settings))
+    }
+
+    /// Returns whether the resolved `@vue/language-server` is a 3.x (or newer) release.
+    fn uses_server_v3(&self, settings: &VueSettings) -> bool {
+        major_version(&self.resolved_server_version(settings)).is_some_and(|major| major >= 3)
+    }
+
+    /// Returns the worktree-relative directory of the nearest `package.json` that declares
+    /// TypeScript as a dependency, if a local copy of TypeScript exists in the worktree.
+    fn local_typescript_directory(
+        &mut self,
+        worktree: &zed::Worktree,
+        settings: &VueSettings,
+    ) -> VueResult<Option<String>> {
+        // Since the extension is not allowed to read the filesystem within the project
+        // except through the worktree (which does not contains `node_modules`), we check
+        // the `package.json` files to see if `typescript` is listed in the dependencies.
+        self.local_dependency_directory(worktree, settings, TYPESCRIPT_PACKAGE_NAME)
+    }
+
+    /// Returns the worktree-relative directory of the nearest `package.json` that declares
+    /// the given package as a dependency.
+    fn local_dependency_directory(
+        &mut self,
+        worktree: &zed::Worktree,
+        settings: &VueSettings,
+        package_name: &str,
+    ) -> VueResult<Option<String>> {
+        Ok(self
+            .local_dependency(worktree, settings, package_name)?
+            .map(|(directory, _)| directory))
+    }
+
+    /// Returns the worktree-relative directory of the nearest `package.json` that declares
+    /// the given package as a dependency, with the version range it declares.
+    ///
+    /// Like Node's module resolution, a dependency declared in any ancestor of the project
+    /// directory counts.
+    fn local_dependency(
+        &mut self,
+        worktree: &zed::Worktree,
+        settings: &VueSettings,
+        package_name: &str,
+    ) -> VueResult<Option<(String, Option<String>)>> {
+        let declared_version = |package_json: &PackageJson| {
+            package_json
+                .dependency_version(package_name)
+                .map(str::to_owned)
+        };
+        if let Some((directory, package_json)) =
+            read_nested_package_jsons(worktree, &settings.project_directory)?
+                .into_iter()
+                .find(|(_, package_json)| package_json.has_dependency(package_name))
+        {
+            return Ok(Some((directory, declared_version(&package_json))));
+        }
+
+        Ok(self
+            .package_json(worktree)?
+            .filter(|package_json| package_json.has_dependency(package_name))
+            .map(|package_json| (String::new(), declared_version(package_json))))
+    }
+
+    /// Returns the parsed root `package.json` of the worktree, if it has one.
+    ///
+    /// The file is only read and parsed again when a different worktree is encountered.
+    fn package_json(&mut self, worktree: &zed::Worktree) -> VueResult<Option<&PackageJson>> {
+        let worktree_root = worktree.root_path();
+        let is_stale = self
+            .package_json_cache
+            .as_ref()
+            .is_none_or(|cache| cache.worktree_root != worktree_root);
+        if is_stale {
+            self.package_json_cache = Some(PackageJsonCache {
+                package_json: read_package_json_at(worktree, "package.json")?,
+                worktree_root,
+            });
+        }
+
+        Ok(self
+            .package_json_cache
+            .as_ref()
+            .and_then(|cache| cache.package_json.as_ref()))
+    }
+
+    /// Returns the major version of Vue the worktree depends on, if it declares it.
+    fn detect_vue_major_version(&mut self, worktree: &zed::Worktree) -> Option<u64> {
+        let package_json = self.package_json(worktree).ok()??;
+        major_version(package_json.dependency_version(VUE_PACKAGE_NAME)?)
+    }
+
+    /// Warns about dependencies on older Vue tooling, which can register a second TypeScript
+    /// plugin and cause duplicate diagnostics.
+    fn warn_about_legacy_tooling(&mut self, worktree: &zed::Worktree) {
+        let Ok(Some(package_json)) = self.package_json(worktree) else {
+            return;
+        };
+
+        for package_name in package_json.legacy_tooling_packages() {
+            log_info!(
+                "warning: the project depends on {package_name}, which may conflict with \
+                {PACKAGE_NAME} and report duplicate diagnostics"
+            );
+        }
+    }
+
+    /// Returns the absolute path to the server script of the worktree's own
+    /// `@vue/language-server`, if the project declares it, with the version range it declares.
+    ///
+    /// The script is looked up in the `node_modules` next to the `package.json` declaring the
+    /// package, since package managers like pnpm only link it there.
+    fn local_server(
+        &mut self,
+        worktree: &zed::Worktree,
+        settings: &VueSettings,
+    ) -> VueResult<Option<(String, Option<String>)>> {
+        let Some((directory, version)) = self.local_dependency(worktree, settings, PACKAGE_NAME)?
+        else {
+            return Ok(None);
+        };
+
+        let relative_path = std::path::Path::new(&directory).join(SERVER_PATH);
+        // The worktree usually does not expose `node_modules`, so a script we cannot read is
+        // no proof that it is missing.
+        if worktree
+            .read_text_file(&relative_path.to_string_lossy())
+            .is_err()
+        {
+            log_debug!(
+                "could not verify '{}' through the worktree",
+                relative_path.display()
+            );
+        }
+
+        Ok(Some((
+            worktree_root(worktree)
+                .join(relative_path)
+                .to_string_lossy()
+                .to_string(),
+            version,
+        )))
+    }
+
+    fn install_typescript_if_needed(
+        &mut self,
+        language_server_id: &zed::LanguageServerId,
+        worktree: &zed::Worktree,
+        settings: &VueSettings,
+    ) -> VueResult<()> {
+        self.typescript_tsdk_path = None;
+
+        // An SDK configured by absolute path is used as it is, e.g. a TypeScript kept by a
+        // toolchain manager, so nothing needs to be installed.
+        if let Some(tsdk) = settings
+            .typescript
+            .tsdk
+            .clone()
+            .filter(|tsdk| is_absolute_host_path(tsdk))
+        {
+            let tsserverlibrary = std::path::Path::new(&tsdk).join(TSSERVERLIBRARY_FILE_NAME);
+            if inspect_configured_path(&tsserverlibrary, "typescript.tsdk")
+                .is_some_and(|stat| !stat.is_file())
+            {
+                log_info!(
+                    "warning: '{tsdk}' does not contain {TSSERVERLIBRARY_FILE_NAME}, \
+                    type information may be unavailable"
+                );
+            }
+            log_info!("using the configured TypeScript SDK at '{tsdk}'");
+            self.typescript_tsdk_path = Some(tsdk);
+            return Ok(());
+        }
+
+        // Yarn Plug'n'Play projects expose TypeScript through the editor SDK generated by
+        // `yarn dlx @yarnpkg/sdks`, as there is no `node_modules` to find it in.
+        if uses_yarn_pnp(worktree)
+            && worktree
+                .read_text_file(&format!("{YARN_SDK_TSDK_PATH}/typescript.js"))
+                .is_ok()
+        {
+            let tsdk_path = worktree_root(worktree)
+                .join(YARN_SDK_TSDK_PATH)
+                .to_string_lossy()
+                .to_string();
+            log_info!("found Yarn PnP TypeScript SDK at '{tsdk_path}'");
+            self.typescript_tsdk_path = Some(tsdk_path);
+            return Ok(());
+        }
+
+        if let Some(directory) = self
+            .local_typescript_directory(worktree, settings)
+            .unwrap_or_default()
+        {
+            let tsdk_path = project_tsdk_path(&worktree_root(worktree), &directory)
+                .to_string_lossy()
+                .to_string();
+            log_info!("found local TypeScript installation at '{tsdk_path}'");
+            self.typescript_tsdk_path = Some(tsdk_path);
+            if let Ok(Some(managed_version)) = self.npm.installed_version(TYPESCRIPT_PACKAGE_NAME) {
+                log_info!(
+                    "warning: an extension-managed {TYPESCRIPT_PACKAGE_NAME}@{managed_version} \
+                    is also installed, the server is pointed at the local installation instead"
+                );
+            }
+            return Ok(());
+        }
+
+        let installed_typescript_version = self.npm.installed_version(TYPESCRIPT_PACKAGE_NAME)?;
+        let typescript_version = match settings.typescript.version.clone() {
+            Some(pinned_version) if pinned_version != LATEST_VERSION => pinned_version,
+            _ => self.npm_package_latest_version(TYPESCRIPT_PACKAGE_NAME)?,
+        };
+
+        let typescript_version = match installed_typescript_version.clone() {
+            Some(installed_version) if self.offline => {
+                log_info!(
+                    "offline mode: using the installed \
+                    {TYPESCRIPT_PACKAGE_NAME}@{installed_version}"
+                );
+                installed_version
+            }
+            None if self.offline => {
+                return Err(VueExtensionError::Offline {
+                    package_name: TYPESCRIPT_PACKAGE_NAME.to_string(),
+                    version: typescript_version,
+                });
+            }
+            _ => typescript_version,
+        };
+
+        if !self.offline
+            && (self.force_reinstall
+                || installed_typescript_version.as_ref() != Some(&typescript_version))
+        {
+            log_info!("installing {TYPESCRIPT_PACKAGE_NAME}@{typescript_version}");
+            install_dependent_package(
+                self.npm.as_ref(),
+                language_server_id,
+                TYPESCRIPT_PACKAGE_NAME,
+                &typescript_version,
+            )?;
+        } else {
+            log_info!("typescript already installed");
+            log_debug!("using {TYPESCRIPT_PACKAGE_NAME}@{typescript_version}");
+        }
+
+        let server_version = self.resolved_server_version(settings);
+        if let Some(supported_majors) = supported_typescript_majors(&server_version) {
+            if major_version(&typescript_version)
+                .is_some_and(|major| !supported_majors.contains(&major))
+            {
+                log_info!(
+                    "warning: {TYPESCRIPT_PACKAGE_NAME}@{typescript_version} may not be compatible \
+                    with {PACKAGE_NAME}@{server_version}, consider pinning `typescript.version` \
+                    to a {}.x release",
+                    supported_majors.end()
+                );
+            }
+        }
+
+        let tsdk_path = managed_tsdk_path()?;
+        log_debug!("resolved tsdk path to '{tsdk_path}'");
+
+        // Only the managed installation can be read from here, so this is the one SDK we
+        // can check before the server silently runs without types. The server refuses to
+        // start without a `typescript.tsdk`, so the path is sent regardless.
+        let tsserverlibrary_path =
+            std::path::Path::new(TYPESCRIPT_TSDK_PATH).join(TSSERVERLIBRARY_FILE_NAME);
+        if !fs::metadata(&tsserverlibrary_path).is_ok_and(|stat| stat.is_file()) {
+            log_info!(
+                "warning: '{tsdk_path}' does not contain {TSSERVERLIBRARY_FILE_NAME}, \
+                type information may be unavailable"
+            );
+        }
+        self.typescript_tsdk_path = Some(tsdk_path);
+
+        Ok(())
+    }
+
+    /// Installs `@vue/typescript-plugin`, treating a failure as non-fatal.
+    ///
+    /// The Vue server works without the plugin, only with fewer TypeScript features, so a
+    /// failed install is logged and the plugin is not advertised to TypeScript servers.
+    fn install_ts_plugin_or_degrade(
+        &mut self,
+        language_server_id: &zed::LanguageServerId,
+        settings: &VueSettings,
+    ) {
+        match self.install_ts_plugin_if_needed(language_server_id, settings) {
+            Ok(()) => self.ts_plugin_install_failed = false,
+            Err(error) => {
+                // Going without the plugin is expected in offline mode, so it is not a warning.
+                if let VueExtensionError::Offline { .. } = error {
+                    log_info!("offline mode: continuing without {TS_PLUGIN_PACKAGE_NAME}: {error}");
+                } else {
+                    log_info!(
+                        "warning: failed to install {TS_PLUGIN_PACKAGE_NAME}, \
+                        continuing without it: {error}"
+                    );
+                }
+                self.npm.set_installation_status(
+                    language_server_id,
+                    &zed::LanguageServerInstallationStatus::None,
+                );
+                self.ts_plugin_install_failed = true;
+            }
+        }
+    }
+
+    /// Returns the version of `@vue/typescript-plugin` requested for the worktree.
+    ///
+    /// The plugin is released in lockstep with the server, so unless it is configured
+    /// separately it follows the resolved server version, however that was chosen. A known
+    /// server version uses the plugin from its toolchain.
+    fn requested_ts_plugin_version(&self, settings: &VueSettings) -> String {
+        if let Some(version) = &settings.typescript_plugin.version {
+            return version.clone();
+        }
+        let server_version = self.resolved_server_version(settings);
+        match toolchain_for_server(&server_version) {
+            Some(toolchain) => toolchain.plugin_version.to_string(),
+            None => server_version,
+        }
+    }
+
+    fn install_ts_plugin_if_needed(
+        &mut self,
+        language_server_id: &zed::LanguageServerId,
+        settings: &VueSettings,
+    ) -> VueResult<()> {
+        let installed_plugin_version = self.npm.installed_version(TS_PLUGIN_PACKAGE_NAME)?;
+        let plugin_version = match self.requested_ts_plugin_version(settings) {
+            pinned_version if pinned_version != LATEST_VERSION => pinned_version,
+            _ => self.npm_package_latest_version(TS_PLUGIN_PACKAGE_NAME)?,
+        };
+
+        let plugin_version = match installed_plugin_version.clone() {
+            Some(installed_version) if self.offline => {
+                log_info!(
+                    "offline mode: using the installed {TS_PLUGIN_PACKAGE_NAME}@{installed_version}"
+                );
+                installed_version
+            }
+            None if self.offline => {
+                return Err(VueExtensionError::Offline {
+                    package_name: TS_PLUGIN_PACKAGE_NAME.to_string(),
+                    version: plugin_version,
+                });
+            }
+            _ => plugin_version,
+        };
+
+        if !self.offline
+            && (self.force_reinstall || installed_plugin_version.as_ref() != Some(&plugin_version))
+        {
+            log_info!("installing {TS_PLUGIN_PACKAGE_NAME}@{plugin_version}");
+            install_dependent_package(
+                self.npm.as_ref(),
+                language_server_id,
+                TS_PLUGIN_PACKAGE_NAME,
+                &plugin_version,
+            )?;
+        } else {
+            log_info!("ts-plugin already installed");
+            log_debug!("using {TS_PLUGIN_PACKAGE_NAME}@{plugin_version}");
+        }
+        Ok(())
+    }
+
+    /// Returns the `location` to advertise for `@vue/typescript-plugin`.
+    ///
+    /// When the project declares the plugin, this is the directory of the `package.json`
+    /// declaring it, since package managers like pnpm only link it into the `node_modules` next
+    /// to that file.
+    fn get_ts_plugin_root_path(
+        &mut self,
+        worktree: &zed::Worktree,
+        settings: &VueSettings,
+    ) -> VueResult<String> {
+        // Without a folder open, there is no root for a local plugin location to be relative
+        // to, so only the managed plugin can be used.
+        if worktree.root_path().is_empty() {
+            log_info!(
+                "warning: the worktree has no root path, \
+                using the global installation of {TS_PLUGIN_PACKAGE_NAME}"
+            );
+            return self.managed_ts_plugin_location(settings);
+        }
+
+        if let Some(directory) =
+            self.local_dependency_directory(worktree, settings, TS_PLUGIN_PACKAGE_NAME)?
+        {
+            let (os, _arch) = zed::current_platform();
+            let location = ts_plugin_location_for_os(&worktree.root_path(), &directory, os);
+            log_info!("Using local installation of {TS_PLUGIN_PACKAGE_NAME} from '{location}'");
+            return Ok(location);
+        }
+
+        log_info!("Using global installation of {TS_PLUGIN_PACKAGE_NAME}");
+        let location = self.managed_ts_plugin_location(settings)?;
+        log_debug!("resolved {TS_PLUGIN_PACKAGE_NAME} location to '{location}'");
+        Ok(location)
+    }
+
+    /// Returns the `location` configured by `typescript_plugin.location` in the `lsp.vue`
+    /// settings, which replaces the one we would otherwise advertise for the plugin.
+    ///
+    /// A relative location is resolved against the worktree root. The location must be a
+    /// directory whose `node_modules` contains `@vue/typescript-plugin`, which is only checked
+    /// where the extension can read it.
+    fn configured_ts_plugin_location(
+        worktree: &zed::Worktree,
+        settings: &VueSettings,
+    ) -> VueResult<Option<String>> {
+        let Some(location) = settings.typescript_plugin.location.clone() else {
+            return Ok(None);
+        };
+        let plugin_directory = std::path::Path::new("node_modules").join(TS_PLUGIN_PACKAGE_NAME);
+        let missing_plugin_error = || VueExtensionError::InvalidPluginLocation {
+            location: location.clone(),
+            problem: format!("does not contain {}", plugin_directory.display()),
+        };
+
+        if is_absolute_host_path(&location) {
+            if let Some(stat) = inspect_configured_path(
+                std::path::Path::new(&location),
+                "typescript_plugin.location",
+            ) {
+                if !stat.is_dir() {
+                    return Err(VueExtensionError::InvalidPluginLocation {
+                        location,
+                        problem: "is not a directory".to_string(),
+                    });
+                }
+                if !fs::metadata(std::path::Path::new(&location).join(&plugin_directory))
+                    .is_ok_and(|stat| stat.is_dir())
+                {
+                    return Err(missing_plugin_error());
+                }
+            }
+            return Ok(Some(location));
+        }
+
+        let directory = location.trim_start_matches("./");
+        let plugin_package_json = std::path::Path::new(directory)
+            .join(&plugin_directory)
+            .join("package.json");
+        // The worktree usually does not expose `node_modules`, so a plugin we cannot read is no
+        // proof that it is missing.
+        if worktree
+            .read_text_file(&plugin_package_json.to_string_lossy())
+            .is_err()
+        {
+            log_debug!(
+                "could not verify '{}' from the configured typescript_plugin.location",
+                plugin_package_json.display()
+            );
+        }
+        Ok(Some(
+            worktree_root(worktree)
+                .join(directory)
+                .to_string_lossy()
+                .to_string(),
+        ))
+    }
+
+    /// Logs a summary of everything that was resolved to start the server, for pasting into
+    /// bug reports.
+    ///
+    /// Only shown at the `debug` log level.
+    fn log_startup_diagnostics(
+        &mut self,
+        worktree: &zed::Worktree,
+        settings: &VueSettings,
+        command: &zed::Command,
+    ) {
+        if !log_enabled(LogLevel::Debug) {
+            return;
+        }
+
+        let server_version = self
+            .server_version
+            .clone()
+            .unwrap_or_else(|| "project installation".to_string());
+        let local_typescript = match self.local_typescript_directory(worktree, settings) {
+            Ok(Some(directory)) => format!("yes, in '{directory}'"),
+            Ok(None) => "no".to_string(),
+            Err(error) => format!("unknown ({error})"),
+        };
+        let ts_plugin_location = if self.ts_plugin_enabled(settings) {
+            match Self::configured_ts_plugin_location(worktree, settings) {
+                Ok(Some(location)) => location,
+                Err(error) => format!("invalid ({error})"),
+                Ok(None) => match self.local_dependency_directory(
+                    worktree,
+                    settings,
+                    TS_PLUGIN_PACKAGE_NAME,
+                ) {
+                    Ok(Some(directory)) => worktree_root(worktree)
+                        .join(directory)
+                        .to_string_lossy()
+                        .to_string(),
+                    _ => self
+                        .managed_ts_plugin_location(settings)
+                        .unwrap_or_else(|error| format!("unknown ({error})")),
+                },
+            }
+        } else {
+            "disabled".to_string()
+        };
+
+        log_debug!("startup diagnostics:");
+        log_debug!("  {PACKAGE_NAME} version: {server_version}");
+        log_debug!("  command: {} {}", command.command, command.args.join(" "));
+        log_debug!(
+            "  typescript.tsdk: '{}'",
+            self.tsdk_path(worktree, settings)
+        );
+        log_debug!("  local {TYPESCRIPT_PACKAGE_NAME}: {local_typescript}");
+        log_debug!("  {TS_PLUGIN_PACKAGE_NAME} location: '{ts_plugin_location}'");
+        log_debug!("  Yarn PnP: {}", uses_yarn_pnp(worktree));
+    }
+
+    /// Returns the initialization options we send when the user has not overridden them.
+    fn default_initialization_options(
+        &mut self,
+        worktree: &zed::Worktree,
+        settings: &VueSettings,
+    ) -> serde_json::Value {
+        let tsdk_path = self.tsdk_path(worktree, settings);
+
+        // 3.x always runs alongside a TypeScript server and rejects `vue.hybridMode`.
+        if self.uses_server_v3(settings) {
+            return json!({
+                "typescript": {
+                    "tsdk": tsdk_path
+                }
+            });
+        }
+
+        json!({
+            "typescript": {
+                "tsdk": tsdk_path
+            },
+            "vue": {
+                "hybridMode": settings.hybrid_mode(),
+            }
+        })
+    }
+
+    /// Returns the TypeScript SDK path to send to the server.
+    ///
+    /// An explicit `typescript.tsdk` in the `lsp.vue` settings takes precedence over the one
+    /// in `.vscode/settings.json`, which in turn takes precedence over the path we computed
+    /// while installing TypeScript. Before TypeScript has been resolved, e.g. with a custom
+    /// server binary, the managed installation is assumed, which is warned about once.
+    ///
+    /// A path is always returned, as the 2.x server fails to initialize without one.
+    fn tsdk_path(&mut self, worktree: &zed::Worktree, settings: &VueSettings) -> String {
+        if let Some(tsdk_path) = settings
+            .typescript
+            .tsdk
+            .clone()
+            .or_else(|| vscode_tsdk_path(worktree))
+            .or_else(|| self.typescript_tsdk_path.clone())
+        {
+            return tsdk_path;
+        }
+
+        let tsdk_path = managed_tsdk_path().unwrap_or_else(|_| TYPESCRIPT_TSDK_PATH.to_string());
+        if !self.did_warn_about_tsdk {
+            log_info!(
+                "warning: no TypeScript SDK was resolved, assuming the extension-managed one at \
+                '{tsdk_path}', set `typescript.tsdk` in the lsp.vue settings if types are missing"
+            );
+            self.did_warn_about_tsdk = true;
+        }
+        tsdk_path
+    }
+
+    /// Returns the `location` of the extension-managed `@vue/typescript-plugin`.
+    fn managed_ts_plugin_location(&self, settings: &VueSettings) -> VueResult<String> {
+        let global_path = current_dir()?;
+
+        // 3.x no longer needs a separately installed plugin, so we point the
+        // plugin loader at the server package that bundles it.
+        if self.uses_server_v3(settings) {
+            return Ok(global_path
+                .join(SERVER_PACKAGE_PATH)
+                .to_string_lossy()
+                .to_string());
+        }
+
+        Ok(global_path.to_string_lossy().to_string())
+    }
+
+    /// Returns the description of `@vue/typescript-plugin` shared by every TypeScript server
+    /// it is registered with.
+    fn ts_plugin_descriptor(settings: &VueSettings, location: String) -> serde_json::Value {
+        json!({
+            "name": TS_PLUGIN_PACKAGE_NAME,
+            "location": location,
+            "languages": Self::ts_plugin_languages(settings),
+        })
+    }
+
+    /// Returns the initialization options for typescript-language-server, registering
+    /// `@vue/typescript-plugin` when given its location.
+    ///
+    /// The additional plugins and the memory limit apply whether or not the Vue plugin is
+    /// registered. Returns `None` when there is nothing to send.
+    fn typescript_language_server_options(
+        settings: &VueSettings,
+        location: Option<String>,
+    ) -> Option<serde_json::Value> {
+        let plugins = location
+            .map(|location| Self::ts_plugin_descriptor(settings, location))
+            .into_iter()
+            .chain(Self::additional_ts_plugins(settings))
+            .collect::<Vec<_>>();
+
+        let mut options = json!({});
+        if !plugins.is_empty() {
+            options["plugins"] = plugins.into();
+        }
+        if let Some(max_server_memory) = settings.typescript.max_server_memory {
+            options["maxTsServerMemory"] = max_server_memory.into();
+        }
+        non_empty(options)
+    }
+
+    /// Returns the workspace configuration for vtsls, registering `@vue/typescript-plugin`
+    /// when given its location.
+    ///
+    /// The additional plugins and the memory limit apply whether or not the Vue plugin is
+    /// registered. Returns `None` when there is nothing to send.
+    fn vtsls_configuration(
+        settings: &VueSettings,
+        location: Option<String>,
+    ) -> Option<serde_json::Value> {
+        let plugins = location
+            .map(|location| {
+                let mut plugin = Self::ts_plugin_descriptor(settings, location);
+                plugin["enableForWorkspaceTypeScriptVersions"] =
+                    Self::prefer_workspace_typescript(settings).into();
+                plugin
+            })
+            .into_iter()
+            .chain(Self::additional_ts_plugins(settings))
+            .collect::<Vec<_>>();
+
+        let mut configuration = json!({});
+        if !plugins.is_empty() {
+            configuration["vtsls"] = json!({
+                "tsserver": {
+                    "globalPlugins": plugins
+                }
+            });
+        }
+        if let Some(max_server_memory) = settings.typescript.max_server_memory {
+            configuration["typescript"] = json!({
+                "tsserver": {
+                    "maxTsServerMemory": max_server_memory
+                }
+            });
+        }
+        non_empty(configuration)
+    }
+
+    /// Returns whether `@vue/typescript-plugin` should be registered with the given
+    /// TypeScript server.
+    fn registers_ts_plugin(&self, settings: &VueSettings, target_language_server_id: &str) -> bool {
+        self.ts_plugin_enabled(settings)
+            && !self.ts_plugin_install_failed
+            && Self::is_ts_plugin_target(settings, target_language_server_id)
+    }
+
+    /// Returns the tsserver plugins to register after `@vue/typescript-plugin`, as configured
+    /// by `typescript.additional_plugins` in the `lsp.vue` settings.
+    ///
+    /// Entries without a `name` are ignored, while the rest are passed through as they are.
+    fn additional_ts_plugins(settings: &VueSettings) -> Vec<serde_json::Value> {
+        settings
+            .typescript
+            .additional_plugins
+            .iter()
+            .filter(|plugin| {
+                let has_name = plugin.get("name").is_some_and(|name| name.is_string());
+                if !has_name {
+                    log_info!(
+                        "ignoring typescript.additional_plugins entry without a name: {plugin}"
+                    );
+                }
+                has_name
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the languages `@vue/typescript-plugin` is registered for, as configured by
+    /// `typescript_plugin.languages` in the `lsp.vue` settings, followed by any
+    /// `typescript_plugin.additional_languages`.
+    fn ts_plugin_languages(settings: &VueSettings) -> Vec<String> {
+        let mut languages = settings
+            .typescript_plugin
+            .languages
+            .clone()
+            .unwrap_or_else(|| {
+                DEFAULT_TS_PLUGIN_LANGUAGES
+                    .iter()
+                    .map(|language| language.to_string())
+                    .chain([Self::vue_language_id(settings)])
+                    .collect()
+            });
+
+        for language in &settings.typescript_plugin.additional_languages {
+            if !languages.contains(language) {
+                languages.push(language.clone());
+            }
+        }
+
+        languages
+    }
+
+    /// Returns the language identifier of Vue files, as configured by
+    /// `typescript_plugin.vue_language_id` in the `lsp.vue` settings.
+    ///
+    /// Defaults to [`DEFAULT_VUE_LANGUAGE_ID`].
+    fn vue_language_id(settings: &VueSettings) -> String {
+        settings
+            .typescript_plugin
+            .vue_language_id
+            .clone()
+            .unwrap_or_else(|| DEFAULT_VUE_LANGUAGE_ID.to_string())
+    }
+
+    /// Returns whether `@vue/typescript-plugin` is enabled, as configured by
+    /// `typescript_plugin.enabled` in the `lsp.vue` settings.
+    ///
+    /// The plugin is only needed in hybrid mode, so by default it is enabled when hybrid mode
+    /// is, which 3.x always is.
+    fn ts_plugin_enabled(&self, settings: &VueSettings) -> bool {
+        settings
+            .typescript_plugin
+            .enabled
+            .unwrap_or_else(|| self.uses_server_v3(settings) || settings.hybrid_mode())
+    }
+
+    /// Returns whether `@vue/typescript-plugin` has to be installed separately from the server.
+    fn needs_ts_plugin_install(&self, settings: &VueSettings) -> bool {
+        self.ts_plugin_enabled(settings)
+            && !self.uses_server_v3(settings)
+            && settings.typescript_plugin.location.is_none()
+    }
+
+    /// Returns whether `@vue/typescript-plugin` should be registered with the given TypeScript
+    /// server, as configured by `typescript_plugin.target` in the `lsp.vue` settings.
+    ///
+    /// The default, `auto`, registers the plugin with whichever server asks for it.
+    fn is_ts_plugin_target(settings: &VueSettings, target_language_server_id: &str) -> bool {
+        match settings.typescript_plugin.target.as_deref() {
+            Some(target) if target != "auto" => target == target_language_server_id,
+            _ => true,
+        }
+    }
+
+    /// Returns whether the plugin should also attach to the workspace's own TypeScript version,
+    /// as configured by `typescript.prefer_workspace_version` in the `lsp.vue` settings.
+    ///
+    /// Enabled by default.
+    fn prefer_workspace_typescript(settings: &VueSettings) -> bool {
+        settings.typescript.prefer_workspace_version.unwrap_or(true)
+    }
+}
+
+/// Installs a package the server depends on, reporting the download in the server's
+/// installation status.
+fn install_dependent_package(
+    npm: &dyn Npm,
+    language_server_id: &zed::LanguageServerId,
+    package_name: &str,
+    version: &str,
+) -> VueResult<()> {
+    npm.set_installation_status(
+        language_server_id,
+        &zed::LanguageServerInstallationStatus::Downloading,
+    );
+    npm_install_package_with_retry(npm, package_name, version)?;
+    npm.set_installation_status(
+        language_server_id,
+        &zed::LanguageServerInstallationStatus::None,
+    );
+    Ok(())
+}
+
+/// Expands the `inlay_hints` block of the `lsp.vue` settings into the workspace configuration
+/// the server reads its inlay hint options from.
+fn inlay_hint_configuration(settings: &InlayHintSettings) -> Option<serde_json::Value> {
+    let mut inlay_hints = serde_json::Map::new();
+    // Parameter name hints take a mode rather than a flag.
+    if let Some(enabled) = settings.parameter_names {
+        inlay_hints.insert(
+            "parameterNames".to_string(),
+            json!({ "enabled": if enabled { "all" } else { "none" } }),
+        );
+    }
+    for (enabled, option) in [
+        (settings.parameter_types, "parameterTypes"),
+        (settings.variable_types, "variableTypes"),
+        (
+            settings.property_declaration_types,
+            "propertyDeclarationTypes",
+        ),
+        (
+            settings.function_like_return_types,
+            "functionLikeReturnTypes",
+        ),
+        (settings.enum_member_values, "enumMemberValues"),
+    ] {
+        if let Some(enabled) = enabled {
+            inlay_hints.insert(option.to_string(), json!({ "enabled": enabled }));
+        }
+    }
+
+    if inlay_hints.is_empty() {
+        return None;
+    }
+    Some(json!({
+        "typescript": {
+            "inlayHints": inlay_hints
+        }
+    }))
+}
+
+/// Expands the `auto_imports` block of the `lsp.vue` settings into the workspace configuration
+/// the server reads its TypeScript preferences from.
+fn auto_import_configuration(settings: &AutoImportSettings) -> Option<serde_json::Value> {
+    let mut preferences = serde_json::Map::new();
+    if let Some(module_specifier) = &settings.module_specifier {
+        preferences.insert("importModuleSpecifier".to_string(), json!(module_specifier));
+    }
+    if let Some(quote_style) = &settings.quote_style {
+        preferences.insert("quoteStyle".to_string(), json!(quote_style));
+    }
+
+    if preferences.is_empty() {
+        return None;
+    }
+    Some(json!({
+        "typescript": {
+            "preferences": preferences
+        }
+    }))
+}
+
+/// Expands the `html.custom_data` setting into the workspace configuration the server's HTML
+/// service loads custom tags and attributes from.
+///
+/// Files that cannot be read or are not custom data objects are logged and skipped, so that
+/// one broken file does not discard the rest.
+fn custom_data_configuration(
+    worktree: &zed::Worktree,
+    settings: &HtmlSettings,
+) -> Option<serde_json::Value> {
+    let custom_data = settings
+        .custom_data
+        .iter()
+        .map(|path| path.trim_start_matches("./"))
+        .filter(|path| {
+            let custom_data = match worktree.read_text_file(path) {
+                Ok(custom_data) => custom_data,
+                Err(err) => {
+                    log_info!("skipping custom data file '{path}': {err}");
+                    return false;
+                }
+            };
+            match serde_json::from_str::<serde_json::Value>(&custom_data) {
+                Ok(serde_json::Value::Object(_)) => true,
+                Ok(_) => {
+                    log_info!("skipping custom data file '{path}': expected a JSON object");
+                    false
+                }
+                Err(err) => {
+                    log_info!("skipping custom data file '{path}': {err}");
+                    false
+                }
+            }
+        })
+        // The server resolves the paths itself, which it cannot do relative to the worktree.
+        .map(|path| {
+            worktree_root(worktree)
+                .join(path)
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect::<Vec<_>>();
+
+    if custom_data.is_empty() {
+        return None;
+    }
+    Some(json!({
+        "html": {
+            "customData": custom_data
+        }
+    }))
+}
+
+/// The blocks of a Vue file whose initial indentation can be configured.
+const FORMAT_BLOCKS: &[&str] = &["template", "script", "style"];
+
+/// Expands the `format` block of the `lsp.vue` settings into the workspace configuration the
+/// server reads its formatting options from.
+fn format_configuration(settings: &FormatSettings) -> Option<serde_json::Value> {
+    let mut configuration = json!({});
+    for (block, initial_indent) in FORMAT_BLOCKS.iter().zip([
+        settings.initial_indent.template,
+        settings.initial_indent.script,
+        settings.initial_indent.style,
+    ]) {
+        if let Some(initial_indent) = initial_indent {
+            merge_json(
+                &mut configuration,
+                json!({ "vue": { "format": { *block: { "initialIndent": initial_indent } } } }),
+            );
+        }
+    }
+    // The template is formatted by the HTML service, which reads the `html.format` options.
+    if let Some(print_width) = settings.print_width {
+        merge_json(
+            &mut configuration,
+            json!({ "html": { "format": { "wrapLineLength": print_width } } }),
+        );
+    }
+    if let Some(wrap_attributes) = &settings.wrap_attributes {
+        merge_json(
+            &mut configuration,
+            json!({ "html": { "format": { "wrapAttributes": wrap_attributes } } }),
+        );
+    }
+
+    non_empty(configuration)
+}
+
+/// Returns the given JSON object, or `None` if it has no keys.
+fn non_empty(object: serde_json::Value) -> Option<serde_json::Value> {
+    object
+        .as_object()
+        .is_some_and(|object| !object.is_empty())
+        .then_some(object)
+}
+
+/// Decides whether the server package has to be (re)installed.
+///
+/// npm sometimes fails to report the version of a perfectly good installation, so an
+/// existing server with an unknown version is kept unless a reinstall is forced.
+fn needs_server_install(
+    force_reinstall: bool,
+    server_exists: bool,
+    installed_version: Option<&str>,
+    requested_version: &str,
+) -> bool {
+    if force_reinstall || !server_exists {
+        return true;
+    }
+    installed_version.is_some_and(|installed_version| installed_version != requested_version)
+}
+
+/// The inputs deciding whether [`install_server_package`] installs the server package.
+struct ServerInstall {
+    /// The exact version or `next` dist-tag requested.
+    version: String,
+    force_reinstall: bool,
+    offline: bool,
+    /// Whether a usable server script is installed already.
+    server_exists: bool,
+}
+
+/// What [`install_server_package`] did, with the version of the server that is installed.
+#[derive(Debug, PartialEq)]
+enum ServerInstallOutcome {
+    /// Nothing was installed, as the version is installed already or offline mode is on.
+    Kept(String),
+    /// The version was installed.
+    Installed(String),
+    /// Installing the version failed, so the existing installation, of the given version if
+    /// known, is used instead.
+    KeptExisting(Option<String>),
+}
+
+/// Installs the requested version of the server package, unless it is installed already.
+///
+/// Installation statuses are reported through `set_status` rather than the [`Npm`], since
+/// only the caller knows the language server they belong to.
+fn install_server_package(
+    npm: &dyn Npm,
+    set_status: &dyn Fn(zed::LanguageServerInstallationStatus),
+    install: ServerInstall,
+) -> VueResult<ServerInstallOutcome> {
+    let ServerInstall {
+        version,
+        force_reinstall,
+        offline,
+        mut server_exists,
+    } = install;
+    let installed_version = npm.installed_version(PACKAGE_NAME)?;
+    if !needs_server_install(
+        force_reinstall,
+        server_exists,
+        installed_version.as_deref(),
+        &version,
+    ) {
+        return Ok(ServerInstallOutcome::Kept(version));
+    }
+
+    if offline {
+        if !server_exists {
+            return Err(VueExtensionError::Offline {
+                package_name: PACKAGE_NAME.to_string(),
+                version,
+            });
+        }
+        let installed_version = installed_version.unwrap_or(version);
+        log_info!("offline mode: using the installed {PACKAGE_NAME}@{installed_version}");
+        return Ok(ServerInstallOutcome::Kept(installed_version));
+    }
+
+    set_status(zed::LanguageServerInstallationStatus::Downloading);
+    // npm can leave files of the newer version behind when installing an older one over it,
+    // so a downgrade starts from a clean package directory.
+    if let Some(installed_version) = installed_version
+        .as_deref()
+        .filter(|installed_version| is_downgrade(installed_version, &version))
+    {
+        log_info!("removing {PACKAGE_NAME}@{installed_version} before downgrading to {version}");
+        match fs::remove_dir_all(SERVER_PACKAGE_PATH) {
+            // With the old copy gone, a failed install has nothing to fall back to.
+            Ok(()) => server_exists = false,
+            Err(err) => log_info!("warning: failed to remove '{SERVER_PACKAGE_PATH}': {err}"),
+        }
+    }
+
+    match npm_install_package_with_retry(npm, PACKAGE_NAME, &version) {
+        Ok(()) => Ok(ServerInstallOutcome::Installed(version)),
+        Err(error) if server_exists => {
+            // Keep the server usable with the stale copy, but make it clear that the
+            // requested version could not be installed.
+            let message = format!("{error}, using the existing installation instead");
+            log_info!("warning: {message}");
+            set_status(zed::LanguageServerInstallationStatus::Failed(message));
+            Ok(ServerInstallOutcome::KeptExisting(installed_version))
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/// Installs the given npm package, retrying with an increasing delay to ride out transient
+/// network failures.
+///
+/// The installation status stays at `Downloading` while retrying, as the install may still
+/// succeed. Only the error of the final attempt is returned.
+fn npm_install_package_with_retry(
+    npm: &dyn Npm,
+    package_name: &str,
+    version: &str,
+) -> VueResult<()> {
+    let mut attempt = 1;
+    loop {
+        let error = match npm.install(package_name, version) {
+            Ok(()) => return Ok(()),
+            Err(error) if !error.is_transient() || attempt >= NPM_INSTALL_ATTEMPTS => {
+                return Err(error)
+            }
+            Err(error) => error,
+        };
+
+        log_info!("{error} (attempt {attempt} of {NPM_INSTALL_ATTEMPTS}), retrying");
+        std::thread::sleep(NPM_INSTALL_RETRY_DELAY * attempt);
+        attempt += 1;
+    }
+}
+
+/// Returns the built-in highlight for a completion of the given kind.
+fn default_completion_highlight(
+    kind: &CompletionKind,
+    completion: &Completion,
+) -> Option<&'static str> {
+    Some(match kind {
+        CompletionKind::Class | CompletionKind::Interface => "type",
+        CompletionKind::Constructor => "type",
+        CompletionKind::Enum | CompletionKind::Struct => "type",
+        CompletionKind::Constant | CompletionKind::EnumMember => "constant",
+        CompletionKind::Module => "module",
+        CompletionKind::Function | CompletionKind::Method => "function",
+        CompletionKind::Property | CompletionKind::Field | CompletionKind::Value
+            if is_component_completion(kind, completion) =>
+        {
+            COMPONENT_HIGHLIGHT
+        }
+        CompletionKind::Property | CompletionKind::Field => "tag",
+        CompletionKind::Variable => "type",
+        CompletionKind::Keyword | CompletionKind::Snippet => "keyword",
+        CompletionKind::Value => "tag",
+        _ => return None,
+    })
+}
+
+/// Returns the name of the given completion kind, as used in the `completions.highlights`
+/// setting.
+fn completion_kind_name(kind: &CompletionKind) -> Option<&'static str> {
+    Some(match kind {
+        CompletionKind::Text => "Text",
+        CompletionKind::Method => "Method",
+        CompletionKind::Function => "Function",
+        CompletionKind::Constructor => "Constructor",
+        CompletionKind::Field => "Field",
+        CompletionKind::Variable => "Variable",
+        CompletionKind::Class => "Class",
+        CompletionKind::Interface => "Interface",
+        CompletionKind::Module => "Module",
+        CompletionKind::Property => "Property",
+        CompletionKind::Unit => "Unit",
+        CompletionKind::Value => "Value",
+        CompletionKind::Enum => "Enum",
+        CompletionKind::Keyword => "Keyword",
+        CompletionKind::Snippet => "Snippet",
+        CompletionKind::Color => "Color",
+        CompletionKind::File => "File",
+        CompletionKind::Reference => "Reference",
+        CompletionKind::Folder => "Folder",
+        CompletionKind::EnumMember => "EnumMember",
+        CompletionKind::Constant => "Constant",
+        CompletionKind::Struct => "Struct",
+        CompletionKind::Event => "Event",
+        CompletionKind::Operator => "Operator",
+        CompletionKind::TypeParameter => "TypeParameter",
+        CompletionKind::Other(_) => return None,
+    })
+}
+
+/// Returns whether accepting the given completion adds an import.
+///
+/// The extension API does not expose a completion's additional text edits, so we look for
+/// the `Add import from "..."` line that TypeScript leaves in the completion details.
+fn is_auto_import_completion(completion: &Completion) -> bool {
+    completion.detail.as_deref().is_some_and(|detail| {
+        detail.lines().any(|line| {
+            let line = line.trim_start().to_ascii_lowercase();
+            AUTO_IMPORT_DETAIL_PREFIXES
+                .iter()
+                .any(|prefix| line.starts_with(prefix))
+        })
+    })
+}
+
+/// Returns whether the given completion refers to a deprecated API.
+///
+/// The extension API does not expose the LSP `deprecated` flag or completion tags, so we
+/// look for the `@deprecated` marker that TypeScript leaves in the completion details.
+fn is_deprecated_completion(completion: &Completion) -> bool {
+    let label_details = completion.label_details.as_ref();
+    [
+        completion.detail.as_deref(),
+        label_details.and_then(|details| details.detail.as_deref()),
+        label_details.and_then(|details| details.description.as_deref()),
+    ]
+    .into_iter()
+    .flatten()
+    .any(|text| {
+        let text = text.to_ascii_lowercase();
+        text.contains("@deprecated") || text.contains("(deprecated)")
+    })
+}
+
+/// Returns whether the given template completion refers to a Vue component rather than a
+/// plain HTML tag or attribute.
+///
+/// Any completion whose detail is exactly the server's [`COMPONENT_DETAIL`] counts, which is
+/// how kebab-case names such as `my-button` are recognized. By their label alone they cannot be
+/// told apart from CSS properties such as `font-size`, which are completed as properties too.
+/// The HTML language service completes tags as properties, so only those are otherwise
+/// recognized by a PascalCase label.
+fn is_component_completion(kind: &CompletionKind, completion: &Completion) -> bool {
+    let has_component_detail = completion
+        .detail
+        .as_deref()
+        .is_some_and(|detail| detail.trim().eq_ignore_ascii_case(COMPONENT_DETAIL));
+    if has_component_detail {
+        return true;
+    }
+    if !matches!(kind, CompletionKind::Property) {
+        return false;
+    }
+
+    let label = &completion.label;
+    label
+        .chars()
+        .next()
+        .is_some_and(|first| first.is_ascii_uppercase())
+        && label.chars().any(|c| c.is_ascii_lowercase())
+        && label.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Replaces the numbered tabstops (`$1`), placeholders (`${1:text}`), and choices
+/// (`${1|one,two|}`) in the given snippet with the text they insert by default, for display.
+///
+/// Anything else is kept as is, so labels such as `$emit` or `$refs` are shown unchanged.
+fn strip_snippet_placeholders(snippet: &str) -> String {
+    strip_snippet_placeholders_until(&mut snippet.chars().peekable(), false)
+}
+
+/// Strips snippet syntax from `chars`, stopping after the `}` closing a placeholder when
+/// `nested`.
+fn strip_snippet_placeholders_until(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    nested: bool,
+) -> String {
+    let mut text = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            // Within a placeholder, `\}` is a literal brace rather than its end.
+            '\\' if nested && matches!(chars.peek(), Some('}' | '$' | '\\')) => {
+                text.extend(chars.next());
+            }
+            '}' if nested => return text,
+            '$' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                while chars.next_if(char::is_ascii_digit).is_some() {}
+            }
+            '$' => match snippet_placeholder_start(chars) {
+                Some(':') => text.push_str(&strip_snippet_placeholders_until(chars, true)),
+                Some(_) => {
+                    // Only the first choice is shown.
+                    let choices: String = chars.by_ref().take_while(|c| *c != '|').collect();
+                    text.push_str(choices.split(',').next().unwrap_or_default());
+                    chars.next_if_eq(&'}');
+                }
+                None => text.push('$'),
+            },
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+/// Consumes the `{1:` or `{1|` opening a placeholder or choice after a `$`, returning its
+/// `:` or `|`.
+///
+/// Nothing is consumed when `chars` does not start with either.
+fn snippet_placeholder_start(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<char> {
+    let mut lookahead = chars.clone();
+    lookahead.next_if_eq(&'{')?;
+    lookahead.next_if(char::is_ascii_digit)?;
+    while lookahead.next_if(char::is_ascii_digit).is_some() {}
+    let separator = lookahead.next_if(|c| matches!(c, ':' | '|'))?;
+    *chars = lookahead;
+    Some(separator)
+}
+
+/// Truncates the given completion detail to at most `max_length` characters, ending it with
+/// an ellipsis when shortened.
+fn truncate_detail(detail: String, max_length: Option<usize>) -> String {
+    let Some(max_length) = max_length else {
+        return detail;
+    };
+    if detail.chars().count() <= max_length {
+        return detail;
     }
 
-    fn install_ts_plugin_if_needed(&mut self) -> Result<()> {
-        let installed_plugin_version = zed::npm_package_installed_version(TS_PLUGIN_PACKAGE_NAME)?;
-        let latest_plugin_version = zed::npm_package_latest_version(TS_PLUGIN_PACKAGE_NAME)?;
+    let mut truncated = detail
+        .chars()
+        .take(max_length.saturating_sub(1))
+        .collect::<String>();
+    truncated.push('…');
+    truncated
+}
 
-        if installed_plugin_version.as_ref() != Some(&latest_plugin_version) {
-            println!("installing {TS_PLUGIN_PACKAGE_NAME}@{latest_plugin_version}");
-            zed::npm_install_package(TS_PLUGIN_PACKAGE_NAME, &latest_plugin_version)?;
-        } else {
-            println!("ts-plugin already installed");
+/// Recursively merges `overlay` into `base`.
+///
+/// Objects are merged key by key, while any other value in `overlay` (including arrays)
+/// replaces the corresponding value in `base`. A key present in `overlay` is authoritative
+/// even when its value is empty or `null`, so only keys missing from `overlay` keep the
+/// value from `base`.
+fn merge_json(base: &mut serde_json::Value, overlay: serde_json::Value) {
+    match (base, overlay) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_json(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
         }
-        Ok(())
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Checks that the given version is either an exact semver version (e.g. `2.2.8` or
+/// `3.0.0-beta.1`) or one of the dist-tags `latest` and `next`.
+///
+/// Ranges such as `^2.2` are rejected, as they cannot be compared against the installed version.
+fn validate_version(version: &str) -> Result<()> {
+    if version == LATEST_VERSION || version == NEXT_VERSION {
+        return Ok(());
     }
 
-    fn get_ts_plugin_root_path(&self, worktree: &zed::Worktree) -> Result<Option<String>> {
-        let package_json = worktree.read_text_file("package.json")?;
-        let package_json: PackageJson = serde_json::from_str(&package_json)
-            .map_err(|err| format!("failed to parse package.json: {err}"))?;
+    let core = version
+        .split_once(['-', '+'])
+        .map_or(version, |(core, _)| core);
+    let is_exact = core.split('.').count() == 3
+        && core
+            .split('.')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()));
+    if !is_exact {
+        return Err(format!(
+            "'{version}' is not an exact version (like '2.2.8'), \
+            '{LATEST_VERSION}' or '{NEXT_VERSION}'"
+        ));
+    }
 
-        let has_local_plugin = package_json
-            .dev_dependencies
-            .contains_key(TS_PLUGIN_PACKAGE_NAME)
-            || package_json
-                .dependencies
-                .contains_key(TS_PLUGIN_PACKAGE_NAME);
+    Ok(())
+}
 
-        if has_local_plugin {
-            println!("Using local installation of {TS_PLUGIN_PACKAGE_NAME}");
-            return Ok(None);
-        }
+/// Returns the TypeScript major versions known to work with the given version of
+/// `@vue/language-server`, judging by the known toolchains of the same major version.
+fn supported_typescript_majors(
+    server_version: &str,
+) -> Option<&'static std::ops::RangeInclusive<u64>> {
+    let server_major = major_version(server_version)?;
+    TOOLCHAINS
+        .iter()
+        .find(|toolchain| major_version(toolchain.server_version) == Some(server_major))
+        .map(|toolchain| &toolchain.typescript_majors)
+}
 
-        println!("Using global installation of {TS_PLUGIN_PACKAGE_NAME}");
-        Ok(Some(
-            env::current_dir().unwrap().to_string_lossy().to_string(),
-        ))
+/// Returns the major component of the given version or range, ignoring any leading
+/// operators such as `v`, `^`, or `~`.
+fn major_version(version: &str) -> Option<u64> {
+    version
+        .trim_start_matches(|c: char| !c.is_ascii_digit())
+        .split('.')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Returns the numeric `major.minor.patch` components of the given version, ignoring any
+/// prerelease or build suffix.
+fn version_components(version: &str) -> Option<Vec<u64>> {
+    version
+        .trim_start_matches(|c: char| !c.is_ascii_digit())
+        .split(['-', '+'])
+        .next()?
+        .split('.')
+        .map(|component| component.parse().ok())
+        .collect()
+}
+
+/// Returns whether installing `requested_version` over `installed_version` is a downgrade.
+///
+/// Versions that cannot be compared are not considered downgrades.
+fn is_downgrade(installed_version: &str, requested_version: &str) -> bool {
+    match (
+        version_components(installed_version),
+        version_components(requested_version),
+    ) {
+        (Some(installed), Some(requested)) => requested < installed,
+        _ => false,
     }
 }
 
 impl zed::Extension for VueExtension {
     fn new() -> Self {
         Self {
+            npm: Box::new(ZedNpm),
             did_find_server: false,
-            typescript_tsdk_path: TYPESCRIPT_TSDK_PATH.to_owned(),
+            server_worktree_root: None,
+            server_version: None,
+            typescript_tsdk_path: None,
+            did_warn_about_tsdk: false,
+            package_json_cache: None,
+            completion_settings: CompletionSettings::default(),
+            force_reinstall: false,
+            did_force_reinstall: false,
+            vue_major_version: None,
+            project_server_version: None,
+            offline: false,
+            ts_plugin_install_failed: false,
+            latest_versions: HashMap::new(),
         }
     }
 
@@ -178,56 +2874,152 @@ impl zed::Extension for VueExtension {
         language_server_id: &zed::LanguageServerId,
         worktree: &zed::Worktree,
     ) -> Result<zed::Command> {
-        let server_path = self.server_script_path(language_server_id, worktree)?;
-        Ok(zed::Command {
-            command: zed::node_binary_path()?,
-            args: vec![
-                zed_ext::sanitize_windows_path(env::current_dir().unwrap())
-                    .join(&server_path)
+        let settings = VueSettings::for_worktree(worktree);
+        update_log_level(&settings);
+        self.completion_settings = settings.completions.clone();
+
+        // A user-provided binary replaces the managed installation entirely.
+        if let Some(binary) = LspSettings::for_worktree("vue", worktree)
+            .ok()
+            .and_then(|settings| settings.binary)
+        {
+            if let Some(path) = binary.path {
+                log_info!("using the configured language server binary '{path}'");
+                let mut env = Self::server_env(worktree, &settings);
+                for (key, value) in binary.env.unwrap_or_default() {
+                    env.retain(|(existing_key, _)| *existing_key != key);
+                    env.push((key, value));
+                }
+                let command = zed::Command {
+                    command: path,
+                    args: binary.arguments.unwrap_or_default(),
+                    env,
+                };
+                self.log_startup_diagnostics(worktree, &settings, &command);
+                return Ok(command);
+            }
+        }
+
+        // Resolve Node first, so that a missing Node fails before anything is installed.
+        let node_binary_path = Self::node_binary_path(worktree, &settings)?;
+        let server_path = self.server_script_path(language_server_id, worktree, &settings)?;
+
+        let mut args = Vec::new();
+        if uses_yarn_pnp(worktree) {
+            // Under Yarn Plug'n'Play there is no `node_modules` in the project, so the server
+            // has to be started with the PnP runtime loaded to resolve the project's packages.
+            args.push("--require".to_string());
+            args.push(
+                worktree_root(worktree)
+                    .join(YARN_PNP_PATH)
                     .to_string_lossy()
                     .to_string(),
-                "--stdio".to_string(),
-            ],
-            env: Default::default(),
-        })
+            );
+        }
+        // The path is already absolute. It must not be joined onto the extension's directory,
+        // as Windows paths do not count as absolute under WASI and would be appended to it.
+        args.push(server_path);
+        args.push("--stdio".to_string());
+        args.extend(
+            settings
+                .arguments
+                .iter()
+                .filter(|argument| *argument != "--stdio")
+                .cloned(),
+        );
+
+        let command = zed::Command {
+            command: node_binary_path,
+            args,
+            env: Self::server_env(worktree, &settings),
+        };
+        self.log_startup_diagnostics(worktree, &settings, &command);
+        Ok(command)
     }
 
     fn language_server_initialization_options(
         &mut self,
-        _language_server_id: &zed::LanguageServerId,
+        language_server_id: &zed::LanguageServerId,
         worktree: &zed::Worktree,
     ) -> Result<Option<serde_json::Value>> {
-        let initialization_options = LspSettings::for_worktree("vue", worktree)
-            .ok()
-            .and_then(|settings| settings.initialization_options)
-            .unwrap_or_else(|| {
-                json!({
-                    "typescript": {
-                        "tsdk": self.typescript_tsdk_path
-                    },
-                    "vue": {
-                        "hybridMode": false,
-                    }
-                })
-            });
+        let settings = VueSettings::for_worktree(worktree);
+        update_log_level(&settings);
+        let mut initialization_options = self.default_initialization_options(worktree, &settings);
+        for user_options in user_initialization_options(language_server_id, worktree) {
+            merge_json(&mut initialization_options, user_options);
+        }
 
         Ok(Some(initialization_options))
     }
 
+    fn language_server_workspace_configuration(
+        &mut self,
+        _language_server_id: &zed::LanguageServerId,
+        worktree: &zed::Worktree,
+    ) -> Result<Option<serde_json::Value>> {
+        let settings = VueSettings::for_worktree(worktree);
+        update_log_level(&settings);
+        // The server asks for its own `vue.*` section, while the rest of the settings are
+        // the extension's. Those only include the options that are set, so the rest keep the
+        // server's defaults.
+        let mut configuration = settings
+            .vue_configuration
+            .clone()
+            .map(|vue| json!({ "vue": vue }));
+        if let Some(inlay_hint_configuration) = inlay_hint_configuration(&settings.inlay_hints) {
+            merge_json(
+                configuration.get_or_insert_with(|| json!({})),
+                inlay_hint_configuration,
+            );
+        }
+        if let Some(auto_import_configuration) = auto_import_configuration(&settings.auto_imports) {
+            merge_json(
+                configuration.get_or_insert_with(|| json!({})),
+                auto_import_configuration,
+            );
+        }
+        if let Some(format_configuration) = format_configuration(&settings.format) {
+            merge_json(
+                configuration.get_or_insert_with(|| json!({})),
+                format_configuration,
+            );
+        }
+        if let Some(custom_data_configuration) = custom_data_configuration(worktree, &settings.html)
+        {
+            merge_json(
+                configuration.get_or_insert_with(|| json!({})),
+                custom_data_configuration,
+            );
+        }
+        Ok(configuration)
+    }
+
     fn language_server_additional_initialization_options(
         &mut self,
         _language_server_id: &zed::LanguageServerId,
         target_language_server_id: &zed::LanguageServerId,
         worktree: &zed::Worktree,
     ) -> Result<Option<serde_json::Value>> {
-        match target_language_server_id.as_ref() {
-            "typescript-language-server" => Ok(Some(serde_json::json!({
-                "plugins": [{
-                    "name": "@vue/typescript-plugin",
-                    "location": self.get_ts_plugin_root_path(worktree)?.unwrap_or_else(|| worktree.root_path()),
-                    "languages": ["typescript", "vue.js"],
-                }],
-            }))),
+        let settings = VueSettings::for_worktree(worktree);
+        update_log_level(&settings);
+        let target_language_server_id = target_language_server_id.as_ref();
+
+        match target_language_server_id {
+            "typescript-language-server" => {
+                let location = if self.registers_ts_plugin(&settings, target_language_server_id) {
+                    Some(
+                        match Self::configured_ts_plugin_location(worktree, &settings)? {
+                            Some(location) => location,
+                            None => self.get_ts_plugin_root_path(worktree, &settings)?,
+                        },
+                    )
+                } else {
+                    None
+                };
+                Ok(Self::typescript_language_server_options(
+                    &settings, location,
+                ))
+            }
             _ => Ok(None),
         }
     }
@@ -238,19 +3030,27 @@ impl zed::Extension for VueExtension {
         target_language_server_id: &zed::LanguageServerId,
         worktree: &zed::Worktree,
     ) -> Result<Option<serde_json::Value>> {
-        match target_language_server_id.as_ref() {
-            "vtsls" => Ok(Some(serde_json::json!({
-                "vtsls": {
-                    "tsserver": {
-                        "globalPlugins": [{
-                            "name": "@vue/typescript-plugin",
-                            "location": self.get_ts_plugin_root_path(worktree)?.unwrap_or_else(|| worktree.root_path()),
-                            "enableForWorkspaceTypeScriptVersions": true,
-                            "languages": ["typescript", "vue.js"],
-                        }]
-                    }
-                },
-            }))),
+        let settings = VueSettings::for_worktree(worktree);
+        update_log_level(&settings);
+        let target_language_server_id = target_language_server_id.as_ref();
+
+        match target_language_server_id {
+            "vtsls" => {
+                let location = if self.registers_ts_plugin(&settings, target_language_server_id) {
+                    Some(
+                        match Self::configured_ts_plugin_location(worktree, &settings)? {
+                            Some(location) => location,
+                            None if Self::prefer_workspace_typescript(&settings) => {
+                                self.get_ts_plugin_root_path(worktree, &settings)?
+                            }
+                            None => self.managed_ts_plugin_location(&settings)?,
+                        },
+                    )
+                } else {
+                    None
+                };
+                Ok(Self::vtsls_configuration(&settings, location))
+            }
             _ => Ok(None),
         }
     }
@@ -260,32 +3060,30 @@ impl zed::Extension for VueExtension {
         _language_server_id: &zed::LanguageServerId,
         completion: Completion,
     ) -> Option<zed::CodeLabel> {
-        let highlight_name = match completion.kind? {
-            CompletionKind::Class | CompletionKind::Interface => "type",
-            CompletionKind::Constructor => "type",
-            CompletionKind::Constant => "constant",
-            CompletionKind::Function | CompletionKind::Method => "function",
-            CompletionKind::Property | CompletionKind::Field => "tag",
-            CompletionKind::Variable => "type",
-            CompletionKind::Keyword => "keyword",
-            CompletionKind::Value => "tag",
+        self.completion_settings.label_for(completion)
+    }
+
+    fn label_for_symbol(
+        &self,
+        _language_server_id: &zed::LanguageServerId,
+        symbol: Symbol,
+    ) -> Option<zed::CodeLabel> {
+        let highlight_name = match symbol.kind {
+            SymbolKind::Class | SymbolKind::Interface => "type",
+            SymbolKind::Constructor => "type",
+            SymbolKind::Constant => "constant",
+            SymbolKind::Function | SymbolKind::Method => "function",
+            SymbolKind::Property | SymbolKind::Field => "tag",
             _ => return None,
         };
 
-        let len = completion.label.len();
-        let name_span = CodeLabelSpan::literal(completion.label, Some(highlight_name.to_string()));
-
+        let len = symbol.name.len();
         Some(zed::CodeLabel {
             code: Default::default(),
-            spans: if let Some(detail) = completion.detail {
-                vec![
-                    name_span,
-                    CodeLabelSpan::literal(" ", None),
-                    CodeLabelSpan::literal(detail, None),
-                ]
-            } else {
-                vec![name_span]
-            },
+            spans: vec![CodeLabelSpan::literal(
+                symbol.name,
+                Some(highlight_name.to_string()),
+            )],
             filter_range: (0..len).into(),
         })
     }
@@ -301,9 +3099,17 @@ mod zed_ext {
     ///
     /// This is a workaround for https://github.com/bytecodealliance/wasmtime/issues/10415.
     pub fn sanitize_windows_path(path: std::path::PathBuf) -> std::path::PathBuf {
-        use zed_extension_api::{current_platform, Os};
+        let (os, _arch) = zed_extension_api::current_platform();
+        sanitize_for_os(path, os)
+    }
+
+    /// Sanitizes the given path as [`sanitize_windows_path`] would on the given OS.
+    pub fn sanitize_for_os(
+        path: std::path::PathBuf,
+        os: zed_extension_api::Os,
+    ) -> std::path::PathBuf {
+        use zed_extension_api::Os;
 
-        let (os, _arch) = current_platform();
         match os {
             Os::Mac | Os::Linux => path,
             Os::Windows => path
@@ -314,3 +3120,661 @@ mod zed_ext {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use zed_extension_api::Os;
+
+    use super::*;
+
+    #[test]
+    fn sanitize_for_os_trims_the_leading_slash_on_windows() {
+        assert_eq!(
+            zed_ext::sanitize_for_os(PathBuf::from("/C:/Users/me/project"), Os::Windows),
+            PathBuf::from("C:/Users/me/project")
+        );
+    }
+
+    #[test]
+    fn sanitize_for_os_leaves_mac_paths_unchanged() {
+        assert_eq!(
+            zed_ext::sanitize_for_os(PathBuf::from("/Users/me/project"), Os::Mac),
+            PathBuf::from("/Users/me/project")
+        );
+    }
+
+    #[test]
+    fn sanitize_for_os_leaves_linux_paths_unchanged() {
+        assert_eq!(
+            zed_ext::sanitize_for_os(PathBuf::from("/home/me/project"), Os::Linux),
+            PathBuf::from("/home/me/project")
+        );
+    }
+
+    #[test]
+    fn sanitize_for_os_leaves_windows_paths_without_a_leading_slash_unchanged() {
+        assert_eq!(
+            zed_ext::sanitize_for_os(PathBuf::from("C:/Users/me/project"), Os::Windows),
+            PathBuf::from("C:/Users/me/project")
+        );
+    }
+
+    #[test]
+    fn merge_json_overrides_nested_keys_and_keeps_the_rest() {
+        let mut base = serde_json::json!({
+            "vue": { "hybridMode": false, "server": { "maxOldSpaceSize": 2048 } },
+            "typescript": { "tsdk": "/managed/typescript/lib" },
+        });
+        merge_json(
+            &mut base,
+            serde_json::json!({ "vue": { "server": { "maxOldSpaceSize": 4096 } } }),
+        );
+
+        assert_eq!(
+            base,
+            serde_json::json!({
+                "vue": { "hybridMode": false, "server": { "maxOldSpaceSize": 4096 } },
+                "typescript": { "tsdk": "/managed/typescript/lib" },
+            })
+        );
+    }
+
+    #[test]
+    fn merge_json_replaces_arrays() {
+        let mut base = serde_json::json!({ "languages": ["typescript", "javascript"] });
+        merge_json(&mut base, serde_json::json!({ "languages": ["vue"] }));
+
+        assert_eq!(base, serde_json::json!({ "languages": ["vue"] }));
+    }
+
+    #[test]
+    fn merge_json_keeps_an_explicitly_empty_user_tsdk() {
+        let mut initialization_options =
+            serde_json::json!({ "typescript": { "tsdk": "/managed/typescript/lib" } });
+        merge_json(
+            &mut initialization_options,
+            serde_json::json!({ "typescript": { "tsdk": "" } }),
+        );
+
+        assert_eq!(
+            initialization_options,
+            serde_json::json!({ "typescript": { "tsdk": "" } })
+        );
+    }
+
+    #[test]
+    fn vue_settings_parse_a_representative_blob() {
+        let settings = VueSettings::from_value(json!({
+            "project_directory": "apps/web",
+            "offline": true,
+            "env": { "NODE_OPTIONS": "--max-old-space-size=4096" },
+            "arguments": ["--inspect"],
+            "language_server": { "version": "2.2.8" },
+            "typescript": {
+                "tsdk": "/opt/typescript/lib",
+                "max_server_memory": 8192,
+                "additional_plugins": [{ "name": "typescript-styled-plugin" }]
+            },
+            "typescript_plugin": { "target": "vtsls" },
+            "vue": { "hybridMode": true, "server": { "maxFileSize": 1000 } },
+            "format": { "initial_indent": { "script": true }, "print_width": 100 },
+            "completions": { "custom_labels": false }
+        }));
+
+        assert_eq!(settings.project_directory, "apps/web");
+        assert!(settings.offline);
+        assert_eq!(
+            settings.env.get("NODE_OPTIONS").map(String::as_str),
+            Some("--max-old-space-size=4096")
+        );
+        assert_eq!(settings.arguments, ["--inspect"]);
+        assert_eq!(settings.language_server.version.as_deref(), Some("2.2.8"));
+        assert_eq!(
+            settings.typescript.tsdk.as_deref(),
+            Some("/opt/typescript/lib")
+        );
+        assert_eq!(settings.typescript.max_server_memory, Some(8192));
+        assert_eq!(settings.typescript.additional_plugins.len(), 1);
+        assert_eq!(settings.typescript_plugin.target.as_deref(), Some("vtsls"));
+        assert!(settings.hybrid_mode());
+        assert_eq!(
+            settings.vue_configuration,
+            Some(json!({ "hybridMode": true, "server": { "maxFileSize": 1000 } }))
+        );
+        assert_eq!(settings.format.initial_indent.script, Some(true));
+        assert_eq!(settings.format.print_width, Some(100));
+        assert_eq!(settings.completions.custom_labels, Some(false));
+    }
+
+    #[test]
+    fn vue_settings_default_when_empty() {
+        let settings = VueSettings::from_value(json!({}));
+
+        assert_eq!(settings.project_directory, "");
+        assert!(!settings.offline);
+        assert!(settings.env.is_empty());
+        assert!(settings.typescript.tsdk.is_none());
+        assert!(!settings.hybrid_mode());
+        assert!(settings.vue_configuration.is_none());
+    }
+
+    #[test]
+    fn auto_imports_become_typescript_preferences() {
+        let settings = VueSettings::from_value(json!({
+            "autoImports": { "quote_style": "single" }
+        }));
+
+        assert_eq!(
+            auto_import_configuration(&settings.auto_imports),
+            Some(json!({ "typescript": { "preferences": { "quoteStyle": "single" } } }))
+        );
+    }
+
+    #[test]
+    fn vue_settings_accept_camel_case_keys() {
+        let settings = VueSettings::from_value(json!({
+            "projectDirectory": "apps/web",
+            "typescript": { "maxServerMemory": 8192, "preferWorkspaceVersion": false },
+            "typescriptPlugin": { "vueLanguageId": "vue" },
+            "completions": { "customLabels": false, "maxDetailLength": 60 }
+        }));
+
+        assert_eq!(settings.project_directory, "apps/web");
+        assert_eq!(settings.typescript.max_server_memory, Some(8192));
+        assert_eq!(settings.typescript.prefer_workspace_version, Some(false));
+        assert_eq!(
+            settings.typescript_plugin.vue_language_id.as_deref(),
+            Some("vue")
+        );
+        assert_eq!(settings.completions.custom_labels, Some(false));
+        assert_eq!(settings.completions.max_detail_length, Some(60));
+    }
+
+    #[test]
+    fn vue_settings_ignore_values_of_the_wrong_type() {
+        let settings = VueSettings::from_value(json!({
+            "offline": "yes",
+            "registry": "https://registry.example.com",
+            "env": { "GOOD": "1", "BAD": 2 },
+            "arguments": ["--inspect", 3],
+            "typescript": { "tsdk": 42, "version": "5.4.5" },
+            "html": { "custom_data": "data.json" }
+        }));
+
+        assert!(!settings.offline);
+        assert_eq!(
+            settings.registry.as_deref(),
+            Some("https://registry.example.com")
+        );
+        assert_eq!(settings.env.len(), 1);
+        assert_eq!(settings.arguments, ["--inspect"]);
+        assert!(settings.typescript.tsdk.is_none());
+        assert_eq!(settings.typescript.version.as_deref(), Some("5.4.5"));
+        assert!(settings.html.custom_data.is_empty());
+    }
+
+    #[test]
+    fn typescript_servers_register_the_plugin_for_the_same_languages() {
+        let settings = VueSettings::default();
+        let options = VueExtension::typescript_language_server_options(
+            &settings,
+            Some("/plugin".to_string()),
+        )
+        .unwrap();
+        let configuration =
+            VueExtension::vtsls_configuration(&settings, Some("/plugin".to_string())).unwrap();
+
+        assert_eq!(
+            options.pointer("/plugins/0/languages"),
+            configuration.pointer("/vtsls/tsserver/globalPlugins/0/languages")
+        );
+        assert_eq!(
+            options.pointer("/plugins/0/languages"),
+            Some(&json!(["typescript", DEFAULT_VUE_LANGUAGE_ID]))
+        );
+    }
+
+    #[test]
+    fn a_declared_server_range_decides_the_server_major_version() {
+        let mut extension = <VueExtension as zed::Extension>::new();
+        extension.server_version = Some("^3.0.1".to_string());
+
+        assert!(extension.uses_server_v3(&VueSettings::default()));
+    }
+
+    #[test]
+    fn the_ts_plugin_follows_the_project_server_version() {
+        let mut extension = <VueExtension as zed::Extension>::new();
+        extension.project_server_version = Some("2.2.10".to_string());
+
+        assert_eq!(
+            extension.requested_ts_plugin_version(&VueSettings::default()),
+            "2.2.10"
+        );
+    }
+
+    #[test]
+    fn typescript_servers_get_the_memory_limit_without_the_vue_plugin() {
+        let settings = VueSettings::from_value(json!({
+            "typescript": { "maxServerMemory": 8192 }
+        }));
+
+        assert_eq!(
+            VueExtension::typescript_language_server_options(&settings, None),
+            Some(json!({ "maxTsServerMemory": 8192 }))
+        );
+        assert_eq!(
+            VueExtension::vtsls_configuration(&settings, None),
+            Some(json!({ "typescript": { "tsserver": { "maxTsServerMemory": 8192 } } }))
+        );
+        assert_eq!(
+            VueExtension::vtsls_configuration(&VueSettings::default(), None),
+            None
+        );
+    }
+
+    #[test]
+    fn validate_version_accepts_exact_versions() {
+        assert!(validate_version("2.2.8").is_ok());
+    }
+
+    #[test]
+    fn validate_version_accepts_dist_tags() {
+        assert!(validate_version(LATEST_VERSION).is_ok());
+    }
+
+    #[test]
+    fn validate_version_rejects_ranges() {
+        assert!(validate_version("^2.2").is_err());
+    }
+
+    #[test]
+    fn unknown_versions_are_not_retried() {
+        let error = VueExtensionError::install(
+            PACKAGE_NAME,
+            "9.9.9",
+            "npm error code ETARGET\nnpm error notarget No matching version found".to_string(),
+        );
+        assert!(matches!(error, VueExtensionError::UnknownVersion { .. }));
+        assert!(!error.is_transient());
+
+        let error = VueExtensionError::install(
+            PACKAGE_NAME,
+            "2.2.8",
+            "npm error code ECONNRESET".to_string(),
+        );
+        assert!(matches!(error, VueExtensionError::Install { .. }));
+        assert!(error.is_transient());
+    }
+
+    /// An [`Npm`] with a fixed installed version, recording the installs it is asked for.
+    #[derive(Default)]
+    struct MockNpm {
+        installed_version: Option<String>,
+        /// The error message every install fails with, if any.
+        install_error: Option<String>,
+        installs: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl Npm for MockNpm {
+        fn installed_version(&self, _package_name: &str) -> VueResult<Option<String>> {
+            Ok(self.installed_version.clone())
+        }
+
+        fn latest_version(&self, _package_name: &str) -> VueResult<String> {
+            Ok(default_toolchain(None).server_version.to_string())
+        }
+
+        fn install(&self, package_name: &str, version: &str) -> VueResult<()> {
+            self.installs
+                .lock()
+                .unwrap()
+                .push(format!("{package_name}@{version}"));
+            match &self.install_error {
+                Some(message) => Err(VueExtensionError::install(
+                    package_name,
+                    version,
+                    message.clone(),
+                )),
+                None => Ok(()),
+            }
+        }
+
+        fn set_installation_status(
+            &self,
+            _language_server_id: &zed::LanguageServerId,
+            _status: &zed::LanguageServerInstallationStatus,
+        ) {
+        }
+    }
+
+    fn install_server(npm: &MockNpm, version: &str) -> VueResult<ServerInstallOutcome> {
+        install_server_package(
+            npm,
+            &|_| {},
+            ServerInstall {
+                version: version.to_string(),
+                force_reinstall: false,
+                offline: false,
+                server_exists: true,
+            },
+        )
+    }
+
+    #[test]
+    fn install_server_package_installs_a_mismatched_pinned_version_once() {
+        let npm = MockNpm {
+            installed_version: Some("2.2.0".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            install_server(&npm, "2.2.8").unwrap(),
+            ServerInstallOutcome::Installed("2.2.8".to_string())
+        );
+        assert_eq!(
+            *npm.installs.lock().unwrap(),
+            [format!("{PACKAGE_NAME}@2.2.8")]
+        );
+    }
+
+    #[test]
+    fn install_server_package_keeps_a_matching_version() {
+        let npm = MockNpm {
+            installed_version: Some("2.2.8".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            install_server(&npm, "2.2.8").unwrap(),
+            ServerInstallOutcome::Kept("2.2.8".to_string())
+        );
+        assert!(npm.installs.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn install_server_package_tolerates_a_failure_with_an_existing_server() {
+        let npm = MockNpm {
+            installed_version: Some("2.2.0".to_string()),
+            install_error: Some("npm error code ECONNRESET".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            install_server(&npm, "2.2.8").unwrap(),
+            ServerInstallOutcome::KeptExisting(Some("2.2.0".to_string()))
+        );
+        assert_eq!(
+            npm.installs.lock().unwrap().len(),
+            NPM_INSTALL_ATTEMPTS as usize
+        );
+    }
+
+    #[test]
+    fn needs_server_install_keeps_a_server_of_unknown_version() {
+        assert!(!needs_server_install(false, true, None, "2.2.8"));
+    }
+
+    #[test]
+    fn project_tsdk_path_is_built_from_the_worktree_root() {
+        let tsdk_path = project_tsdk_path(std::path::Path::new("/home/me/project"), "app");
+
+        assert_eq!(
+            tsdk_path,
+            PathBuf::from("/home/me/project/app/node_modules/typescript/lib")
+        );
+        assert!(!tsdk_path.starts_with(std::env::current_dir().unwrap()));
+    }
+
+    #[test]
+    fn absolute_host_paths_include_windows_drive_paths() {
+        for path in [
+            "/opt/typescript/lib",
+            "C:\\tools\\typescript",
+            "c:/tools/typescript",
+        ] {
+            assert!(is_absolute_host_path(path), "{path}");
+        }
+        for path in ["tools/typescript", "./lib", "C:", "C:lib"] {
+            assert!(!is_absolute_host_path(path), "{path}");
+        }
+    }
+
+    #[test]
+    fn ts_plugin_locations_are_sanitized_on_windows() {
+        assert_eq!(
+            ts_plugin_location_for_os("/C:/Users/me/project", "app", Os::Windows),
+            "C:/Users/me/project/app"
+        );
+        assert_eq!(
+            ts_plugin_location_for_os("/home/me/project", "app", Os::Linux),
+            "/home/me/project/app"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_server_at_reports_broken_symlinks() {
+        let directory =
+            std::env::temp_dir().join(format!("zed-vue-broken-symlink-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let server_path = directory.join("vue-language-server.js");
+        let _ = fs::remove_file(&server_path);
+        std::os::unix::fs::symlink(directory.join("missing.js"), &server_path).unwrap();
+
+        let error = check_server_at(&server_path.to_string_lossy())
+            .unwrap_err()
+            .to_string();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(
+            error.ends_with("is a symlink to a missing file"),
+            "unexpected error: {error}"
+        );
+    }
+
+    fn completion(label: &str, kind: CompletionKind, detail: Option<&str>) -> Completion {
+        Completion {
+            label: label.to_string(),
+            label_details: None,
+            detail: detail.map(str::to_string),
+            kind: Some(kind),
+            insert_text_format: None,
+        }
+    }
+
+    fn highlight_name(span: &CodeLabelSpan) -> Option<&str> {
+        match span {
+            CodeLabelSpan::Literal(literal) => literal.highlight_name.as_deref(),
+            CodeLabelSpan::CodeRange(_) => panic!("expected a literal span"),
+        }
+    }
+
+    const HANDLED_COMPLETION_KINDS: &[(CompletionKind, &str)] = &[
+        (CompletionKind::Class, "type"),
+        (CompletionKind::Interface, "type"),
+        (CompletionKind::Constructor, "type"),
+        (CompletionKind::Enum, "type"),
+        (CompletionKind::Struct, "type"),
+        (CompletionKind::Constant, "constant"),
+        (CompletionKind::EnumMember, "constant"),
+        (CompletionKind::Module, "module"),
+        (CompletionKind::Function, "function"),
+        (CompletionKind::Method, "function"),
+        (CompletionKind::Property, "tag"),
+        (CompletionKind::Field, "tag"),
+        (CompletionKind::Variable, "type"),
+        (CompletionKind::Keyword, "keyword"),
+        (CompletionKind::Snippet, "keyword"),
+        (CompletionKind::Value, "tag"),
+    ];
+
+    #[test]
+    fn label_for_completion_without_detail_has_a_single_span() {
+        let settings = CompletionSettings::default();
+        for (kind, expected_highlight) in HANDLED_COMPLETION_KINDS {
+            let label = settings
+                .label_for(completion("item", *kind, None))
+                .unwrap_or_else(|| panic!("no label for {kind:?}"));
+
+            assert_eq!(label.spans.len(), 1, "span count for {kind:?}");
+            assert_eq!(
+                highlight_name(&label.spans[0]),
+                Some(*expected_highlight),
+                "highlight for {kind:?}"
+            );
+            assert_eq!(
+                (label.filter_range.start, label.filter_range.end),
+                (0, "item".len() as u32),
+                "filter range for {kind:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn label_for_completion_with_detail_has_three_spans() {
+        let settings = CompletionSettings::default();
+        for (kind, expected_highlight) in HANDLED_COMPLETION_KINDS {
+            let label = settings
+                .label_for(completion("item", *kind, Some("string")))
+                .unwrap_or_else(|| panic!("no label for {kind:?}"));
+
+            // Keyword details are hidden by default.
+            let expected_spans = if matches!(kind, CompletionKind::Keyword) {
+                1
+            } else {
+                3
+            };
+            assert_eq!(label.spans.len(), expected_spans, "span count for {kind:?}");
+            assert_eq!(
+                highlight_name(&label.spans[0]),
+                Some(*expected_highlight),
+                "highlight for {kind:?}"
+            );
+            assert_eq!(
+                (label.filter_range.start, label.filter_range.end),
+                (0, "item".len() as u32),
+                "filter range for {kind:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn label_for_completion_skips_unhandled_kinds() {
+        let settings = CompletionSettings::default();
+
+        assert!(settings
+            .label_for(completion("item", CompletionKind::Text, None))
+            .is_none());
+    }
+
+    #[test]
+    fn strip_snippet_placeholders_replaces_tabstops_placeholders_and_choices() {
+        assert_eq!(
+            strip_snippet_placeholders("v-for=\"$1 in $2\""),
+            "v-for=\" in \""
+        );
+        assert_eq!(
+            strip_snippet_placeholders("v-if=\"${1:condition}\"$0"),
+            "v-if=\"condition\""
+        );
+        assert_eq!(
+            strip_snippet_placeholders("lang=\"${1|ts,tsx|}\""),
+            "lang=\"ts\""
+        );
+        assert_eq!(
+            strip_snippet_placeholders("${1:{ ${2:a\\}b} \\}}"),
+            "{ a}b }"
+        );
+    }
+
+    #[test]
+    fn strip_snippet_placeholders_keeps_vue_instance_properties() {
+        for label in ["$emit", "$refs", "$attrs", "$slots", "${foo}", "$"] {
+            assert_eq!(strip_snippet_placeholders(label), label);
+        }
+    }
+
+    #[test]
+    fn strip_snippet_placeholders_keeps_backslashes() {
+        assert_eq!(
+            strip_snippet_placeholders("C:\\Users\\$emit"),
+            "C:\\Users\\$emit"
+        );
+    }
+
+    #[test]
+    fn kebab_case_tags_are_components() {
+        let pascal_case = completion("MyButton", CompletionKind::Property, None);
+        let kebab_case = completion(
+            "my-button",
+            CompletionKind::Property,
+            Some(COMPONENT_DETAIL),
+        );
+
+        assert!(is_component_completion(
+            &CompletionKind::Property,
+            &pascal_case
+        ));
+        assert!(is_component_completion(
+            &CompletionKind::Property,
+            &kebab_case
+        ));
+        assert_eq!(
+            default_completion_highlight(&CompletionKind::Property, &kebab_case),
+            Some(COMPONENT_HIGHLIGHT)
+        );
+    }
+
+    #[test]
+    fn kebab_case_attributes_and_plain_tags_are_not_components() {
+        let attribute = completion("http-equiv", CompletionKind::Value, None);
+        let tag = completion("div", CompletionKind::Property, None);
+
+        assert!(!is_component_completion(&CompletionKind::Value, &attribute));
+        assert!(!is_component_completion(&CompletionKind::Property, &tag));
+    }
+
+    #[test]
+    fn css_properties_are_not_components() {
+        let property = completion("background-color", CompletionKind::Property, None);
+
+        assert!(!is_component_completion(
+            &CompletionKind::Property,
+            &property
+        ));
+        assert_eq!(
+            default_completion_highlight(&CompletionKind::Property, &property),
+            Some("tag")
+        );
+    }
+
+    #[test]
+    fn script_completions_mentioning_components_are_not_components() {
+        let instance = completion(
+            "proxy",
+            CompletionKind::Field,
+            Some("ComponentPublicInstance"),
+        );
+        let registry = completion(
+            "components",
+            CompletionKind::Property,
+            Some("Record<string, Component>"),
+        );
+        let constant = completion("URL", CompletionKind::Value, None);
+        let acronym = completion("PI", CompletionKind::Property, None);
+
+        assert!(!is_component_completion(&CompletionKind::Field, &instance));
+        assert!(!is_component_completion(
+            &CompletionKind::Property,
+            &registry
+        ));
+        assert!(!is_component_completion(&CompletionKind::Value, &constant));
+        assert!(!is_component_completion(
+            &CompletionKind::Property,
+            &acronym
+        ));
+    }
+}